@@ -0,0 +1,44 @@
+// Times the tokenizer and the parser on a generated template of about 100k tokens, and on blocks
+// nested as deep as they can be. Run with `cargo bench`
+//
+// The parser that recursed for every symbol overflowed the stack on the first template (already at
+// 48k tokens) and took 180ms for 1,500 of its lines, which take 0.7ms now. Nested blocks still
+// scan their body for each level, the second template took 29ms with it and takes 48ms now (tags
+// are checked against the custom delimiters)
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use ptemplator::{parse_tokens, tokenize_text};
+
+const RUNS: u32 = 10;
+
+fn main() {
+    let line = "${var1} a ${if var1}b${endif} c\n";
+    let template = line.repeat(6_250);
+    let tokens = tokenize_text(&template).unwrap();
+    println!(
+        "{} lines, {} tokens ({} a line)",
+        template.lines().count(),
+        tokens.len(),
+        tokenize_text(line).unwrap().len()
+    );
+    let tokenize = time(|| tokenize_text(black_box(&template)).unwrap().len());
+    println!("tokenize: {:?} per run", tokenize);
+    let parse = time(|| parse_tokens(black_box(&tokens)).unwrap().len());
+    println!("parse:    {:?} per run", parse);
+
+    let nested = format!("{}b\n{}", "${if var1}".repeat(256), "${endif}".repeat(256)).repeat(50);
+    let tokens = tokenize_text(&nested).unwrap();
+    println!("256 nested blocks, {} tokens", tokens.len());
+    let parse = time(|| parse_tokens(black_box(&tokens)).unwrap().len());
+    println!("parse:    {:?} per run", parse);
+}
+
+fn time<T>(run: impl Fn() -> T) -> Duration {
+    black_box(run());
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        black_box(run());
+    }
+    start.elapsed() / RUNS
+}
@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ptemplator::{render_with_diagnostics, SymbolTable, TextGenOptions};
+
+// any template renders (or reports its problems) without panicking
+fuzz_target!(|data: &[u8]| {
+    let symbols = SymbolTable::new(&[("name", "world"), ("items", "a,b"), ("text", "a\n  b")]);
+    for lenient in [false, true] {
+        let options = TextGenOptions {
+            lenient,
+            reindent: true,
+            ..Default::default()
+        };
+        let _ = render_with_diagnostics(data, &symbols, &options);
+    }
+});
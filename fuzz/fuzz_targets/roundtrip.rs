@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// texts without tags render back to themselves
+fuzz_target!(|data: &[u8]| {
+    if let Ok(text) = std::str::from_utf8(data) {
+        if let Err(mismatch) = ptemplator::verify_roundtrip(text) {
+            panic!("{:?}: {}", text, mismatch);
+        }
+    }
+});
@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use ptemplator::{create_tokens, parse_tokens_with_errors, reconstruct_text};
+
+// every line is tokenized on its own, gives back its text and parses without panicking
+fuzz_target!(|data: &[u8]| {
+    if let Ok(text) = std::str::from_utf8(data) {
+        for line in text.lines() {
+            let tokens = create_tokens(line.to_string(), 0).unwrap();
+            assert_eq!(reconstruct_text(&tokens), line);
+            parse_tokens_with_errors(&tokens);
+        }
+    }
+});
@@ -0,0 +1,1748 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::sync::Mutex;
+
+use crate::builtins::Builtins;
+use crate::filters::Filters;
+use crate::front::{
+    append_text, collect_definitions, parse_tokens_with_delimiters, reconstruct_text,
+    tokenize_text, Condition, Delimiters, Identifier, LineRange, Macro, ParseError, Position,
+    Range, Symbol, SymbolTable, Token,
+};
+use crate::glob::{glob_files, is_glob};
+#[cfg(feature = "http")]
+use crate::http::HttpProvider;
+use crate::newline::LineEnding;
+use crate::value::Value;
+
+#[derive(Debug, PartialEq)]
+pub enum TextGenError {
+    VariableNotFound(Range),
+    FailedToReadFile(Range),
+    IncludeCycle(Range),
+    InvalidInclude(Range, Box<ParseError>),
+    UnknownFilter(Range),
+    LineRangeOutOfBounds(Range),
+    NoMatchingFiles(Range),
+    ExpansionCycle(Range),
+    ExpansionTooDeep(Range),
+    InvalidValue(Range, Box<ParseError>),
+    UndefinedMacro(Range),
+    WrongArgumentCount(Range),
+    RecursiveMacro(Range),
+    InvalidPosition(Range),
+}
+
+impl TextGenError {
+    /// Part of the template the error is about
+    pub fn range(&self) -> Range {
+        match self {
+            Self::VariableNotFound(range)
+            | Self::FailedToReadFile(range)
+            | Self::IncludeCycle(range)
+            | Self::InvalidInclude(range, _)
+            | Self::UnknownFilter(range)
+            | Self::LineRangeOutOfBounds(range)
+            | Self::NoMatchingFiles(range)
+            | Self::ExpansionCycle(range)
+            | Self::ExpansionTooDeep(range)
+            | Self::InvalidValue(range, _)
+            | Self::UndefinedMacro(range)
+            | Self::WrongArgumentCount(range)
+            | Self::RecursiveMacro(range)
+            | Self::InvalidPosition(range) => *range,
+        }
+    }
+
+    // Same error about `range` instead
+    fn at(self, range: Range) -> Self {
+        match self {
+            Self::VariableNotFound(_) => Self::VariableNotFound(range),
+            Self::FailedToReadFile(_) => Self::FailedToReadFile(range),
+            Self::IncludeCycle(_) => Self::IncludeCycle(range),
+            Self::InvalidInclude(_, err) => Self::InvalidInclude(range, err),
+            Self::UnknownFilter(_) => Self::UnknownFilter(range),
+            Self::LineRangeOutOfBounds(_) => Self::LineRangeOutOfBounds(range),
+            Self::NoMatchingFiles(_) => Self::NoMatchingFiles(range),
+            Self::ExpansionCycle(_) => Self::ExpansionCycle(range),
+            Self::ExpansionTooDeep(_) => Self::ExpansionTooDeep(range),
+            Self::InvalidValue(_, err) => Self::InvalidValue(range, err),
+            Self::UndefinedMacro(_) => Self::UndefinedMacro(range),
+            Self::WrongArgumentCount(_) => Self::WrongArgumentCount(range),
+            Self::RecursiveMacro(_) => Self::RecursiveMacro(range),
+            Self::InvalidPosition(_) => Self::InvalidPosition(range),
+        }
+    }
+
+    pub(crate) fn message(&self) -> String {
+        match self {
+            Self::VariableNotFound(_) => "variable not found".to_string(),
+            Self::FailedToReadFile(_) => "failed to read file".to_string(),
+            Self::IncludeCycle(_) => "file includes itself".to_string(),
+            Self::InvalidInclude(_, err) => format!("invalid included file ({})", err),
+            Self::UnknownFilter(_) => "unknown filter".to_string(),
+            Self::LineRangeOutOfBounds(_) => "line range is out of the file".to_string(),
+            Self::NoMatchingFiles(_) => "no files match the pattern".to_string(),
+            Self::ExpansionCycle(_) => "value of the variable expands to itself".to_string(),
+            Self::ExpansionTooDeep(_) => format!(
+                "values are nested more than {} levels deep",
+                MAX_EXPANSION_DEPTH
+            ),
+            Self::InvalidValue(_, err) => format!("invalid value ({})", err),
+            Self::UndefinedMacro(_) => "macro is not defined".to_string(),
+            Self::WrongArgumentCount(_) => "wrong number of arguments for the macro".to_string(),
+            Self::RecursiveMacro(_) => "macro calls itself".to_string(),
+            Self::InvalidPosition(_) => "output position is out of the text".to_string(),
+        }
+    }
+
+    /// Name of the kind of error, which stays the same when the message changes
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::VariableNotFound(_) => "variable_not_found",
+            Self::FailedToReadFile(_) => "failed_to_read_file",
+            Self::IncludeCycle(_) => "include_cycle",
+            Self::InvalidInclude(_, _) => "invalid_include",
+            Self::UnknownFilter(_) => "unknown_filter",
+            Self::LineRangeOutOfBounds(_) => "line_range_out_of_bounds",
+            Self::NoMatchingFiles(_) => "no_matching_files",
+            Self::ExpansionCycle(_) => "expansion_cycle",
+            Self::ExpansionTooDeep(_) => "expansion_too_deep",
+            Self::InvalidValue(_, _) => "invalid_value",
+            Self::UndefinedMacro(_) => "undefined_macro",
+            Self::WrongArgumentCount(_) => "wrong_argument_count",
+            Self::RecursiveMacro(_) => "recursive_macro",
+            Self::InvalidPosition(_) => "invalid_position",
+        }
+    }
+}
+
+impl fmt::Display for TextGenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let start_pos = self.range().start_pos;
+        write!(
+            f,
+            "{}:{}: {}",
+            start_pos.line + 1,
+            start_pos.column + 1,
+            self.message()
+        )
+    }
+}
+
+impl std::error::Error for TextGenError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::InvalidInclude(_, err) | Self::InvalidValue(_, err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+// Shift of the source positions caused by the edits done so far. The column shift only applies
+// to `column_line`, the source line where the last edit ended.
+#[derive(Default, Clone, Copy)]
+struct Offset {
+    line: isize,
+    column_line: usize,
+    column: isize,
+}
+
+impl Offset {
+    fn apply(&self, pos: &Position) -> Result<Position, TextGenError> {
+        let line = pos.line.checked_add_signed(self.line);
+        let column = if pos.line == self.column_line {
+            pos.column.checked_add_signed(self.column)
+        } else {
+            Some(pos.column)
+        };
+        match (line, column) {
+            (Some(line), Some(column)) => Ok(Position { line, column }),
+            _ => Err(TextGenError::InvalidPosition(Range {
+                start_pos: *pos,
+                end_pos: *pos,
+            })),
+        }
+    }
+
+    // `original` was replaced by output that ends at `new_end`
+    fn replaced(&mut self, original: &Range, new_end: &Position) {
+        self.moved(&original.end_pos, new_end);
+    }
+
+    // Source text after `source` continues from `output`
+    fn moved(&mut self, source: &Position, output: &Position) {
+        self.line = output.line as isize - source.line as isize;
+        self.column_line = source.line;
+        self.column = output.column as isize - source.column as isize;
+    }
+
+    fn removed(&mut self, original: &Range) -> Result<(), TextGenError> {
+        let new_end = self.apply(&original.start_pos)?;
+        self.replaced(original, &new_end);
+        Ok(())
+    }
+}
+
+/// Settings of the text generation
+pub struct TextGenOptions {
+    /// Filters that can be used by `${var|filter}`
+    pub filters: Filters,
+    /// Placeholders of variables that are not defined are written out as they are instead of
+    /// failing, so that a later pass can fill them
+    pub lenient: bool,
+    /// Lines after the first one of multi-line values (such as spread files) are indented like the
+    /// placeholder, which keeps indentation sensitive formats (yaml) valid
+    pub reindent: bool,
+    /// Written between the files of a spread whose variable is a glob (`./parts/*.txt`)
+    pub spread_separator: String,
+    /// Where spread files are read from, the first provider that handles the location is used
+    pub content_providers: Vec<Box<dyn ContentProvider>>,
+    /// Delimiters of the tags of the template and the files spread into it
+    pub delimiters: Delimiters,
+    /// Placeholders in the values of variables (`--greeting 'Hello ${name}'`) are expanded too, up
+    /// to `MAX_EXPANSION_DEPTH` levels deep
+    pub expand_values: bool,
+    /// Content of spread files is read once per render, turn it off for files that change while
+    /// the template is rendered
+    pub cache_spreads: bool,
+    /// Line ending of the rendered text, the one of the template when not given. Used by the
+    /// `render` functions, the generated tokens don't have line breaks
+    pub line_ending: Option<LineEnding>,
+}
+
+impl Default for TextGenOptions {
+    fn default() -> Self {
+        TextGenOptions {
+            filters: Filters::default(),
+            lenient: false,
+            reindent: false,
+            spread_separator: "\n".to_string(),
+            content_providers: default_content_providers(),
+            delimiters: Delimiters::default(),
+            expand_values: false,
+            cache_spreads: true,
+            line_ending: None,
+        }
+    }
+}
+
+/// Source of the content spread into templates (`${...snippet}`), picked by the location the
+/// variable holds. Providers are shared by templates rendered in parallel
+pub trait ContentProvider: Send + Sync {
+    /// Whether the provider can read `location`
+    fn handles(&self, location: &str) -> bool;
+
+    fn read(&self, location: &str) -> io::Result<String>;
+
+    /// Locations matching the pattern `location`, providers without globs only list `location`
+    fn expand(&self, location: &str) -> Vec<String> {
+        vec![location.to_string()]
+    }
+
+    /// Same value for every location of the same content, used to detect spread cycles
+    fn canonical(&self, location: &str) -> io::Result<String> {
+        Ok(location.to_string())
+    }
+}
+
+/// Files of the local file system, locations can be globs (`./parts/*.txt`)
+pub struct FileSystemProvider;
+
+impl ContentProvider for FileSystemProvider {
+    fn handles(&self, _location: &str) -> bool {
+        true
+    }
+
+    fn read(&self, location: &str) -> io::Result<String> {
+        fs::read_to_string(location)
+    }
+
+    fn expand(&self, location: &str) -> Vec<String> {
+        if is_glob(location) {
+            glob_files(location)
+        } else {
+            vec![location.to_string()]
+        }
+    }
+
+    fn canonical(&self, location: &str) -> io::Result<String> {
+        fs::canonicalize(location).map(|path| path.to_string_lossy().to_string())
+    }
+}
+
+/// Provider that reads each location from `provider` once and keeps the content, for templates
+/// rendered in parallel that spread the same files. Failed reads are tried again
+pub struct CachingProvider {
+    provider: Box<dyn ContentProvider>,
+    cache: Mutex<HashMap<String, String>>,
+}
+
+impl CachingProvider {
+    pub fn new(provider: Box<dyn ContentProvider>) -> Self {
+        CachingProvider {
+            provider,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl ContentProvider for CachingProvider {
+    fn handles(&self, location: &str) -> bool {
+        self.provider.handles(location)
+    }
+
+    fn read(&self, location: &str) -> io::Result<String> {
+        if let Some(content) = self.cache.lock().unwrap().get(location) {
+            return Ok(content.clone());
+        }
+        // the lock is not held while reading so that other locations can be read meanwhile
+        let content = self.provider.read(location)?;
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(location.to_string(), content.clone());
+        Ok(content)
+    }
+
+    fn expand(&self, location: &str) -> Vec<String> {
+        self.provider.expand(location)
+    }
+
+    fn canonical(&self, location: &str) -> io::Result<String> {
+        self.provider.canonical(location)
+    }
+}
+
+fn default_content_providers() -> Vec<Box<dyn ContentProvider>> {
+    #[cfg(feature = "http")]
+    let providers: Vec<Box<dyn ContentProvider>> =
+        vec![Box::new(HttpProvider), Box::new(FileSystemProvider)];
+    #[cfg(not(feature = "http"))]
+    let providers: Vec<Box<dyn ContentProvider>> = vec![Box::new(FileSystemProvider)];
+    providers
+}
+
+// State shared by the whole text generation
+struct Context<'a> {
+    options: &'a TextGenOptions,
+    // Stack of files currently being spread (as given by `ContentProvider::canonical`), used to
+    // detect cycles
+    includes: Vec<String>,
+    // Stack of variables whose values are being expanded, used to detect cycles
+    expanding: Vec<Identifier>,
+    builtins: &'a Builtins,
+    spread_cache: &'a mut SpreadCache,
+    // Macros defined so far and the stack of the ones being called (to detect recursion)
+    macros: &'a mut HashMap<Identifier, Macro>,
+    calling: Vec<Identifier>,
+    errors: Vec<TextGenError>,
+}
+
+// Content of the spread files read so far (as it is read, the lines are selected from it on each
+// spread) by canonical location
+type SpreadCache = HashMap<String, String>;
+
+impl Context<'_> {
+    // Symbols inside loops are generated more than once, but their errors are reported once
+    fn report(&mut self, err: TextGenError) {
+        if !self.errors.contains(&err) {
+            self.errors.push(err);
+        }
+    }
+}
+
+pub fn to_output_tokens(
+    symbols: &[Symbol],
+    symbol_table: &SymbolTable,
+) -> Result<Vec<Token>, TextGenError> {
+    to_output_tokens_with_options(symbols, symbol_table, &TextGenOptions::default())
+}
+
+pub fn to_output_tokens_with_options(
+    symbols: &[Symbol],
+    symbol_table: &SymbolTable,
+    options: &TextGenOptions,
+) -> Result<Vec<Token>, TextGenError> {
+    let (tokens, errors) = to_output_tokens_with_errors(symbols, symbol_table, options);
+    match errors.into_iter().next() {
+        Some(err) => Err(err),
+        None => Ok(tokens),
+    }
+}
+
+/// Generate as much of the output as possible, along with every error found on the way. Symbols
+/// that can't be generated are left out of the output
+pub fn to_output_tokens_with_errors(
+    symbols: &[Symbol],
+    symbol_table: &SymbolTable,
+    options: &TextGenOptions,
+) -> (Vec<Token>, Vec<TextGenError>) {
+    let builtins = Builtins::new();
+    let mut spread_cache = SpreadCache::new();
+    let mut macros = HashMap::new();
+    let mut context = Context {
+        options,
+        includes: vec![],
+        expanding: vec![],
+        builtins: &builtins,
+        spread_cache: &mut spread_cache,
+        macros: &mut macros,
+        calling: vec![],
+        errors: vec![],
+    };
+    let tokens = generate_tokens(symbols, symbol_table, &mut Offset::default(), &mut context);
+    (tokens, context.errors)
+}
+
+/// Generates the output of a template given in pieces (whole lines with every block closed), the
+/// output positions of each piece continue from the previous one
+pub(crate) struct Generator<'a> {
+    options: &'a TextGenOptions,
+    offset: Offset,
+    // shared by the pieces so that they see the same values
+    builtins: Builtins,
+    spread_cache: SpreadCache,
+    macros: HashMap<Identifier, Macro>,
+}
+
+impl<'a> Generator<'a> {
+    pub(crate) fn new(options: &'a TextGenOptions) -> Self {
+        Generator {
+            options,
+            offset: Offset::default(),
+            builtins: Builtins::new(),
+            spread_cache: SpreadCache::new(),
+            macros: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn generate(
+        &mut self,
+        symbols: &[Symbol],
+        symbol_table: &SymbolTable,
+    ) -> (Vec<Token>, Vec<TextGenError>) {
+        let mut context = Context {
+            options: self.options,
+            includes: vec![],
+            expanding: vec![],
+            builtins: &self.builtins,
+            spread_cache: &mut self.spread_cache,
+            macros: &mut self.macros,
+            calling: vec![],
+            errors: vec![],
+        };
+        let tokens = generate_tokens(symbols, symbol_table, &mut self.offset, &mut context);
+        (tokens, context.errors)
+    }
+}
+
+fn generate_tokens(
+    symbols: &[Symbol],
+    symbol_table: &SymbolTable,
+    offset: &mut Offset,
+    context: &mut Context,
+) -> Vec<Token> {
+    let mut usage = HashMap::new();
+    line_usage(symbols, &mut usage);
+    let mut tokens = vec![];
+    if let Err(err) = emit_symbols(symbols, symbol_table, &usage, offset, context, &mut tokens) {
+        // the output positions after it can't be worked out
+        context.report(err);
+    }
+    tokens
+}
+
+fn emit_symbols(
+    symbols: &[Symbol],
+    symbol_table: &SymbolTable,
+    usage: &HashMap<usize, LineUsage>,
+    offset: &mut Offset,
+    context: &mut Context,
+    tokens: &mut Vec<Token>,
+) -> Result<(), TextGenError> {
+    for (i, symbol) in symbols.iter().enumerate() {
+        match symbol {
+            Symbol::Conditional {
+                condition,
+                body,
+                open_range,
+                close_range,
+            } => {
+                if condition_holds(condition, symbol_table) {
+                    offset.removed(&tag_extent(open_range, usage))?;
+                    emit_symbols(body, symbol_table, usage, offset, context, tokens)?;
+                    offset.removed(&tag_extent(close_range, usage))?;
+                } else if is_alone(open_range, usage) && is_alone(close_range, usage) {
+                    offset.removed(&whole_lines(open_range, close_range))?;
+                } else {
+                    offset.removed(&symbol.range())?;
+                }
+            }
+            Symbol::Loop {
+                variable,
+                iterable,
+                body,
+                open_range,
+                close_range,
+            } => {
+                let items = match symbol_table.get_value(iterable) {
+                    Some(value) => list_items(value),
+                    None if context.options.lenient => {
+                        // the tags are kept so that the loop can be expanded by a later pass
+                        let delimiters = &context.options.delimiters;
+                        let open_tag = delimiters.tag(&format!("for {} in {}", variable, iterable));
+                        let close_tag = delimiters.tag("endfor");
+                        emit_verbatim(&open_tag, open_range, offset, tokens)?;
+                        emit_symbols(body, symbol_table, usage, offset, context, tokens)?;
+                        emit_verbatim(&close_tag, close_range, offset, tokens)?;
+                        continue;
+                    }
+                    None => {
+                        context.report(TextGenError::VariableNotFound(*open_range));
+                        offset.removed(&symbol.range())?;
+                        continue;
+                    }
+                };
+                if items.is_empty() {
+                    if is_alone(open_range, usage) && is_alone(close_range, usage) {
+                        offset.removed(&whole_lines(open_range, close_range))?;
+                    } else {
+                        offset.removed(&symbol.range())?;
+                    }
+                    continue;
+                }
+                let open_extent = tag_extent(open_range, usage);
+                let close_extent = tag_extent(close_range, usage);
+                offset.removed(&open_extent)?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        // the body is written again right after the previous iteration
+                        let end = offset.apply(&close_extent.start_pos)?;
+                        offset.moved(&open_extent.end_pos, &end);
+                    }
+                    let symbol_table = symbol_table.bind(variable, item.clone());
+                    emit_symbols(body, &symbol_table, usage, offset, context, tokens)?;
+                }
+                offset.removed(&close_extent)?;
+            }
+            // macros can be called once they are defined (so a streamed template can call them)
+            Symbol::Definition {
+                open_range,
+                close_range,
+                ..
+            } => {
+                collect_definitions(std::slice::from_ref(symbol), context.macros);
+                if is_alone(open_range, usage) && is_alone(close_range, usage) {
+                    offset.removed(&whole_lines(open_range, close_range))?;
+                } else {
+                    offset.removed(&symbol.range())?;
+                }
+            }
+            Symbol::Comment { .. } => offset.removed(&comment_extent(symbols, i, usage))?,
+            _ if is_tag_indentation(symbol, usage) => {}
+            _ => match to_token(symbol, symbol_table, *offset, context) {
+                // the line breaks of the template are tokens of their own
+                Ok(Token::Word { text, range })
+                    if text.contains('\n') && !matches!(symbol, Symbol::Word { .. }) =>
+                {
+                    let indentation = if context.options.reindent {
+                        indentation(tokens, &range.start_pos)
+                    } else {
+                        String::new()
+                    };
+                    let end = emit_lines(&text, &range.start_pos, &indentation, tokens);
+                    offset.replaced(&symbol.range(), &end);
+                }
+                Ok(token) => {
+                    offset.replaced(&symbol.range(), &token.range().end_pos);
+                    tokens.push(token);
+                }
+                Err(err) => {
+                    context.report(err);
+                    offset.removed(&symbol.range())?;
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+// Tokens can't span lines, so each line of `text` gets its own token. Returns where the text ends
+fn emit_lines(
+    text: &str,
+    start_pos: &Position,
+    indentation: &str,
+    tokens: &mut Vec<Token>,
+) -> Position {
+    let mut end = *start_pos;
+    for (i, line) in text.split('\n').enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let (start_pos, line) = match i {
+            0 => (*start_pos, line.to_string()),
+            // blank lines are not indented to avoid trailing whitespace
+            _ if line.is_empty() => (
+                Position {
+                    line: start_pos.line + i,
+                    column: 0,
+                },
+                String::new(),
+            ),
+            _ => (
+                Position {
+                    line: start_pos.line + i,
+                    column: 0,
+                },
+                format!("{}{}", indentation, line),
+            ),
+        };
+        let range = calculate_replacement_range(
+            &Range {
+                start_pos,
+                end_pos: start_pos,
+            },
+            &line,
+        );
+        end = range.end_pos;
+        if !line.is_empty() {
+            tokens.push(Token::Word { text: line, range });
+        }
+    }
+    end
+}
+
+// Indentation of the output line `pos` is on, up to `pos`. It's the whitespace already written
+// to the line, or spaces when the line has other text before `pos`
+fn indentation(tokens: &[Token], pos: &Position) -> String {
+    let line_tokens: Vec<&Token> = tokens
+        .iter()
+        .rev()
+        .take_while(|token| token.start_pos().line == pos.line)
+        .collect();
+    let is_indentation = line_tokens
+        .iter()
+        .all(|token| token.to_string().chars().all(char::is_whitespace));
+    if !is_indentation {
+        return " ".repeat(pos.column);
+    }
+    let mut indentation = String::new();
+    let mut column = 0;
+    for token in line_tokens.into_iter().rev() {
+        let start_column = token.start_pos().column;
+        indentation.push_str(&" ".repeat(start_column.saturating_sub(column)));
+        let text = token.to_string();
+        column = start_column + text.chars().count();
+        indentation.push_str(&text);
+    }
+    indentation.push_str(&" ".repeat(pos.column.saturating_sub(column)));
+    indentation
+}
+
+fn emit_verbatim(
+    text: &str,
+    original: &Range,
+    offset: &mut Offset,
+    tokens: &mut Vec<Token>,
+) -> Result<(), TextGenError> {
+    let range = calculate_new_range(original, *offset)?;
+    let range = calculate_replacement_range(&range, text);
+    offset.replaced(original, &range.end_pos);
+    tokens.push(Token::Word {
+        text: text.to_string(),
+        range,
+    });
+    Ok(())
+}
+
+fn condition_holds(condition: &Condition, symbol_table: &SymbolTable) -> bool {
+    match condition {
+        Condition::Defined(identifier) => symbol_table.get_variable(identifier).is_some(),
+        Condition::Equals(identifier, value) => {
+            symbol_table.get_variable(identifier).as_ref() == Some(value)
+        }
+    }
+}
+
+// Items of a list value. Strings are treated as a json array or comma separated values
+fn list_items(value: Value) -> Vec<Value> {
+    match value {
+        Value::List(items) => items,
+        Value::Map(entries) => entries.into_values().collect(),
+        Value::String(text) => match serde_json::from_str::<serde_json::Value>(&text) {
+            Ok(serde_json::Value::Array(items)) => items.into_iter().map(Value::from).collect(),
+            _ if text.trim().is_empty() => vec![],
+            _ => text
+                .split(',')
+                .map(|item| Value::from(item.trim()))
+                .collect(),
+        },
+    }
+}
+
+// What is on a source line, used to find block tags that are alone in their line
+#[derive(Default)]
+struct LineUsage {
+    // whitespace is not counted
+    symbols: usize,
+    has_tag: bool,
+}
+
+fn line_usage(symbols: &[Symbol], usage: &mut HashMap<usize, LineUsage>) {
+    for symbol in symbols {
+        match symbol {
+            Symbol::Conditional {
+                body,
+                open_range,
+                close_range,
+                ..
+            }
+            | Symbol::Loop {
+                body,
+                open_range,
+                close_range,
+                ..
+            } => {
+                for range in [open_range, close_range] {
+                    let line_usage = usage.entry(range.start_pos.line).or_default();
+                    line_usage.symbols += 1;
+                    line_usage.has_tag = true;
+                }
+                line_usage(body, usage);
+            }
+            Symbol::Comment { range, .. } => {
+                let line_usage = usage.entry(range.start_pos.line).or_default();
+                line_usage.symbols += 1;
+                line_usage.has_tag = true;
+            }
+            // the body is not written where it's defined, so a definition in a single line is
+            // alone in it too
+            Symbol::Definition {
+                open_range,
+                close_range,
+                ..
+            } => {
+                let mut lines = vec![open_range.start_pos.line, close_range.start_pos.line];
+                lines.dedup();
+                for line in lines {
+                    let line_usage = usage.entry(line).or_default();
+                    line_usage.symbols += 1;
+                    line_usage.has_tag = true;
+                }
+            }
+            _ if is_whitespace(symbol) => {}
+            _ => {
+                usage
+                    .entry(symbol.range().start_pos.line)
+                    .or_default()
+                    .symbols += 1
+            }
+        }
+    }
+}
+
+fn is_whitespace(symbol: &Symbol) -> bool {
+    matches!(symbol, Symbol::Word { text, .. } if text.chars().all(char::is_whitespace))
+}
+
+// Part of the source removed with the comment `symbols[i]`. Comments that are alone in their line
+// are removed along with the line. Otherwise the space on one side of the comment goes with it
+// (when there is space on the other side too), so that it's neither doubled nor left trailing
+fn comment_extent(symbols: &[Symbol], i: usize, usage: &HashMap<usize, LineUsage>) -> Range {
+    let range = symbols[i].range();
+    if is_alone(&range, usage) {
+        return whole_lines(&range, &range);
+    }
+    let line = range.start_pos.line;
+    let previous_end = i
+        .checked_sub(1)
+        .map(|previous| symbols[previous].range().end_pos)
+        .filter(|end_pos| end_pos.line == line);
+    let next_start = symbols
+        .get(i + 1)
+        .map(|next| next.range().start_pos)
+        .filter(|start_pos| start_pos.line == line);
+    let spaced_after = next_start.is_none_or(|start_pos| start_pos.column > range.end_pos.column)
+        || symbols.get(i + 1).is_some_and(is_whitespace);
+    match (previous_end, next_start) {
+        (Some(start_pos), _) if spaced_after => Range {
+            start_pos,
+            end_pos: range.end_pos,
+        },
+        // the comment starts the line, its indentation is kept
+        (None, Some(end_pos)) => Range {
+            start_pos: range.start_pos,
+            end_pos,
+        },
+        _ => range,
+    }
+}
+
+fn is_alone(range: &Range, usage: &HashMap<usize, LineUsage>) -> bool {
+    usage
+        .get(&range.start_pos.line)
+        .is_some_and(|line_usage| line_usage.symbols == 1)
+}
+
+// Whitespace around a tag that is alone in its line goes away with the line
+fn is_tag_indentation(symbol: &Symbol, usage: &HashMap<usize, LineUsage>) -> bool {
+    is_whitespace(symbol)
+        && usage
+            .get(&symbol.range().start_pos.line)
+            .is_some_and(|line_usage| line_usage.symbols == 1 && line_usage.has_tag)
+}
+
+// Tags that are alone in their line are removed along with the line
+fn tag_extent(range: &Range, usage: &HashMap<usize, LineUsage>) -> Range {
+    if is_alone(range, usage) {
+        whole_lines(range, range)
+    } else {
+        *range
+    }
+}
+
+fn whole_lines(first: &Range, last: &Range) -> Range {
+    Range {
+        start_pos: Position {
+            line: first.start_pos.line,
+            column: 0,
+        },
+        end_pos: Position {
+            line: last.end_pos.line + 1,
+            column: 0,
+        },
+    }
+}
+
+fn to_token(
+    symbol: &Symbol,
+    symbol_table: &SymbolTable,
+    offset: Offset,
+    context: &mut Context,
+) -> Result<Token, TextGenError> {
+    let range = calculate_new_range(&symbol.range(), offset)?;
+    match symbol {
+        Symbol::Word { text, .. } => Ok(Token::Word {
+            text: text.to_string(),
+            range: calculate_replacement_range(&range, text),
+        }),
+        Symbol::Replace {
+            identifier,
+            default,
+            format,
+            filters,
+            range: original_range,
+        } => {
+            let text = match symbol_table
+                .get_variable(identifier)
+                .or_else(|| context.builtins.get(identifier, format.as_deref()))
+                .or_else(|| default.clone())
+            {
+                Some(value) => {
+                    let value =
+                        expand_value(identifier, value, original_range, symbol_table, context)?;
+                    apply_filters(value, filters, &context.options.filters, original_range)?
+                }
+                None if context.options.lenient => {
+                    let filters: String = filters.iter().map(|name| format!("|{}", name)).collect();
+                    context
+                        .options
+                        .delimiters
+                        .tag(&format!("{}{}", identifier, filters))
+                }
+                None => return Err(TextGenError::VariableNotFound(*original_range)),
+            };
+            let range = calculate_replacement_range(&range, &text);
+            Ok(Token::Word { text, range })
+        }
+        Symbol::Spread {
+            identifier,
+            lines,
+            range: original_range,
+        } => {
+            let text = match symbol_table.get_variable(identifier) {
+                Some(location) => {
+                    expand_files(&location, lines, original_range, symbol_table, context)?
+                }
+                None if context.options.lenient => match lines {
+                    Some(lines) => context
+                        .options
+                        .delimiters
+                        .tag(&format!("...{}:{}", identifier, lines)),
+                    None => context
+                        .options
+                        .delimiters
+                        .tag(&format!("...{}", identifier)),
+                },
+                None => return Err(TextGenError::VariableNotFound(*original_range)),
+            };
+            let range = calculate_replacement_range(&range, &text);
+            Ok(Token::Word { text, range })
+        }
+        Symbol::Call {
+            name,
+            arguments,
+            range: original_range,
+        } => {
+            let text = call_macro(name, arguments, original_range, symbol_table, context)?;
+            let range = calculate_replacement_range(&range, &text);
+            Ok(Token::Word { text, range })
+        }
+        Symbol::Conditional { .. }
+        | Symbol::Loop { .. }
+        | Symbol::Comment { .. }
+        | Symbol::Definition { .. } => {
+            unreachable!("blocks and comments are handled by emit_symbols")
+        }
+    }
+}
+
+fn apply_filters(
+    value: String,
+    names: &[Identifier],
+    filters: &Filters,
+    range: &Range,
+) -> Result<String, TextGenError> {
+    names.iter().try_fold(value, |value, name| {
+        filters
+            .get(name)
+            .map(|filter| filter.apply(&value))
+            .ok_or(TextGenError::UnknownFilter(*range))
+    })
+}
+
+/// How deep values can be nested when `expand_values` is set
+pub const MAX_EXPANSION_DEPTH: usize = 16;
+
+// Run the value of `identifier` through the whole pipeline with the same symbols (if that is
+// enabled and there is a tag in it). Errors in the value are reported at the placeholder (`range`)
+fn expand_value(
+    identifier: &str,
+    value: String,
+    range: &Range,
+    symbol_table: &SymbolTable,
+    context: &mut Context,
+) -> Result<String, TextGenError> {
+    if !context.options.expand_values || !value.contains(&context.options.delimiters.open_text()) {
+        return Ok(value);
+    }
+    if context
+        .expanding
+        .iter()
+        .any(|expanding| expanding == identifier)
+    {
+        return Err(TextGenError::ExpansionCycle(*range));
+    }
+    if context.expanding.len() >= MAX_EXPANSION_DEPTH {
+        return Err(TextGenError::ExpansionTooDeep(*range));
+    }
+    let tokens =
+        tokenize_text(&value).map_err(|err| TextGenError::InvalidValue(*range, Box::new(err)))?;
+    let (symbols, errors) = parse_tokens_with_delimiters(&tokens, &context.options.delimiters);
+    if let Some(err) = errors.into_iter().next() {
+        return Err(TextGenError::InvalidValue(*range, Box::new(err)));
+    }
+    context.expanding.push(identifier.to_string());
+    let errors = std::mem::take(&mut context.errors);
+    let output_tokens = generate_tokens(&symbols, symbol_table, &mut Offset::default(), context);
+    let value_errors = std::mem::replace(&mut context.errors, errors);
+    context.expanding.pop();
+    match value_errors.into_iter().next() {
+        Some(err) => Err(err.at(*range)),
+        None => Ok(reconstruct_text(&output_tokens)),
+    }
+}
+
+// Body of the macro `name` with its parameters bound to `arguments`. Errors in the body are
+// reported at the call (`range`)
+fn call_macro(
+    name: &str,
+    arguments: &[String],
+    range: &Range,
+    symbol_table: &SymbolTable,
+    context: &mut Context,
+) -> Result<String, TextGenError> {
+    let definition = context
+        .macros
+        .get(name)
+        .cloned()
+        .ok_or(TextGenError::UndefinedMacro(*range))?;
+    if definition.parameters.len() != arguments.len() {
+        return Err(TextGenError::WrongArgumentCount(*range));
+    }
+    if context.calling.iter().any(|calling| calling == name) {
+        return Err(TextGenError::RecursiveMacro(*range));
+    }
+    let symbol_table = definition
+        .parameters
+        .iter()
+        .zip(arguments)
+        .fold(symbol_table.clone(), |table, (parameter, argument)| {
+            table.bind(parameter, Value::from(argument.as_str()))
+        });
+    context.calling.push(name.to_string());
+    let errors = std::mem::take(&mut context.errors);
+    let output_tokens = generate_tokens(
+        &definition.body,
+        &symbol_table,
+        &mut Offset::default(),
+        context,
+    );
+    let body_errors = std::mem::replace(&mut context.errors, errors);
+    context.calling.pop();
+    if let Some(err) = body_errors.into_iter().next() {
+        return Err(err.at(*range));
+    }
+    let mut text = String::new();
+    append_text(
+        &output_tokens,
+        &mut definition.start.clone(),
+        "\n",
+        &mut text,
+    );
+    Ok(text)
+}
+
+// Run the content of the file through the whole pipeline with the same symbols
+// Every file matching `location` (if the provider supports globs) is expanded on its own, in the
+// order the provider lists them
+fn expand_files(
+    location: &str,
+    lines: &Option<LineRange>,
+    range: &Range,
+    symbol_table: &SymbolTable,
+    context: &mut Context,
+) -> Result<String, TextGenError> {
+    let options = context.options;
+    let provider = options
+        .content_providers
+        .iter()
+        .find(|provider| provider.handles(location))
+        .ok_or(TextGenError::FailedToReadFile(*range))?;
+    let locations = provider.expand(location);
+    if locations.is_empty() {
+        return Err(TextGenError::NoMatchingFiles(*range));
+    }
+    let texts = locations
+        .iter()
+        .map(|location| {
+            expand_file(
+                provider.as_ref(),
+                location,
+                lines,
+                range,
+                symbol_table,
+                context,
+            )
+        })
+        .collect::<Result<Vec<String>, TextGenError>>()?;
+    Ok(texts.join(&options.spread_separator))
+}
+
+fn expand_file(
+    provider: &dyn ContentProvider,
+    location: &str,
+    lines: &Option<LineRange>,
+    range: &Range,
+    symbol_table: &SymbolTable,
+    context: &mut Context,
+) -> Result<String, TextGenError> {
+    let canonical = provider
+        .canonical(location)
+        .map_err(|_| TextGenError::FailedToReadFile(*range))?;
+    if context.includes.contains(&canonical) {
+        return Err(TextGenError::IncludeCycle(*range));
+    }
+    let content = match context.spread_cache.get(&canonical) {
+        Some(content) => content.clone(),
+        None => {
+            let content = provider
+                .read(location)
+                .map_err(|_| TextGenError::FailedToReadFile(*range))?;
+            if context.options.cache_spreads {
+                context
+                    .spread_cache
+                    .insert(canonical.clone(), content.clone());
+            }
+            content
+        }
+    };
+    let content = select_lines(content, lines, range)?;
+    let tokens = tokenize_text(&content)
+        .map_err(|err| TextGenError::InvalidInclude(*range, Box::new(err)))?;
+    let (symbols, errors) = parse_tokens_with_delimiters(&tokens, &context.options.delimiters);
+    if let Some(err) = errors.into_iter().next() {
+        return Err(TextGenError::InvalidInclude(*range, Box::new(err)));
+    }
+    context.includes.push(canonical);
+    let output_tokens = generate_tokens(&symbols, symbol_table, &mut Offset::default(), context);
+    context.includes.pop();
+    Ok(reconstruct_text(&output_tokens))
+}
+
+// Selected lines are inserted as they are, whole files are trimmed
+fn select_lines(
+    content: String,
+    lines: &Option<LineRange>,
+    range: &Range,
+) -> Result<String, TextGenError> {
+    let lines = match lines {
+        Some(lines) => lines,
+        None => return Ok(content.trim().to_string()),
+    };
+    let file_lines: Vec<&str> = content.lines().collect();
+    let end = lines.end.unwrap_or(file_lines.len());
+    if lines.start > file_lines.len() || end > file_lines.len() {
+        return Err(TextGenError::LineRangeOutOfBounds(*range));
+    }
+    Ok(file_lines[lines.start - 1..end].join("\n"))
+}
+
+fn calculate_new_range(range: &Range, offset: Offset) -> Result<Range, TextGenError> {
+    let start_pos = offset.apply(&range.start_pos)?;
+    let end_pos = offset.apply(&range.end_pos)?;
+    if (start_pos.line, start_pos.column) > (end_pos.line, end_pos.column) {
+        return Err(TextGenError::InvalidPosition(*range));
+    }
+    Ok(Range { start_pos, end_pos })
+}
+
+// Range of `text` when it is placed at the start of `range`
+fn calculate_replacement_range(range: &Range, text: &str) -> Range {
+    let start_pos = range.start_pos;
+    let end_pos = Position {
+        line: start_pos.line,
+        column: start_pos.column + text.chars().count(),
+    };
+    Range { start_pos, end_pos }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::front::{
+        create_tokens, parse_tokens, parse_tokens_with_delimiters, reconstruct_text, tokenize_text,
+        Delimiters, Position, Range, SymbolTable,
+    };
+    use crate::value::Value;
+
+    use super::{
+        calculate_new_range, to_output_tokens, to_output_tokens_with_options, CachingProvider,
+        ContentProvider, Offset, TextGenError, TextGenOptions,
+    };
+
+    fn render_text(text: &str, symbol_table: &SymbolTable) -> String {
+        let tokens = tokenize_text(text).unwrap();
+        let symbols = parse_tokens(&tokens).unwrap();
+        reconstruct_text(&to_output_tokens(&symbols, symbol_table).unwrap())
+    }
+
+    fn render_line(line: &str, symbol_table: &SymbolTable) -> String {
+        let tokens = create_tokens(line.to_string(), 0).unwrap();
+        let symbols = parse_tokens(&tokens).unwrap();
+        reconstruct_text(&to_output_tokens(&symbols, symbol_table).unwrap())
+    }
+
+    #[test]
+    fn test_replace_shorter_value() {
+        let symbol_table = SymbolTable::new(&[("var1", "a")]);
+        assert_eq!(
+            render_line("Hello ${var1} world!", &symbol_table),
+            "Hello a world!"
+        );
+    }
+
+    #[test]
+    fn test_replace_longer_value() {
+        let symbol_table = SymbolTable::new(&[("var1", "my friend"), ("var2", "!!")]);
+        assert_eq!(
+            render_line("Hello ${var1}${var2} bye", &symbol_table),
+            "Hello my friend!! bye"
+        );
+    }
+
+    #[test]
+    fn test_replace_default_value() {
+        let symbol_table = SymbolTable::new(&[("var1", "a")]);
+        assert_eq!(
+            render_line("${var1:-b} ${var2:-c d}!", &symbol_table),
+            "a c d!"
+        );
+    }
+
+    #[test]
+    fn test_escaped_replace() {
+        let symbol_table = SymbolTable::new(&[("var1", "a")]);
+        assert_eq!(
+            render_line("\\${var1} is ${var1} \\$ \\${...var1}", &symbol_table),
+            "${var1} is a \\$ ${...var1}"
+        );
+    }
+
+    #[test]
+    fn test_inline_conditional() {
+        let symbol_table = SymbolTable::new(&[("var1", "a"), ("var2", "b")]);
+        assert_eq!(
+            render_line(
+                "[${if var1}${var1}${endif}|${if var3}c${endif}|${if var2 == b}${var2}!${endif}]",
+                &symbol_table
+            ),
+            "[a||b!]"
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        let symbol_table = SymbolTable::new(&[("var1", "a")]);
+        assert_eq!(
+            render_text(
+                "start\n  ${# note about var1}\n${var1} ${# trailing}\nb ${# mid} c${# end}\n  ${# first} d\nend",
+                &symbol_table
+            ),
+            "start\na\nb c\n  d\nend"
+        );
+    }
+
+    #[test]
+    fn test_builtin_variables() {
+        let symbol_table = SymbolTable::new(&[("__hostname", "box")]);
+        let output = render_line(
+            "${__hostname} ${__date:%Y} ${__uuid}|${__uuid}",
+            &symbol_table,
+        );
+        let parts: Vec<&str> = output.split(' ').collect();
+        assert_eq!(parts[0], "box");
+        assert!(parts[1].len() == 4 && parts[1].chars().all(|c| c.is_ascii_digit()));
+        let (first, second) = parts[2].split_once('|').unwrap();
+        assert_eq!(first.len(), 36);
+        assert_eq!(first, second);
+        // names of other variables can have `_`s too
+        let symbol_table = SymbolTable::new(&[("my_var", "a")]);
+        assert_eq!(
+            render_line("${my_var} ${my _var}", &symbol_table),
+            "a ${my _var}"
+        );
+    }
+
+    #[test]
+    fn test_unicode() {
+        let symbol_table = SymbolTable::new(&[("var1", "日本"), ("var2", "ö")]);
+        assert_eq!(
+            render_text(
+                "ä ${var1} 🎉 ${var2}!\n  ${var2|upper} ${x:-ü} é",
+                &symbol_table
+            ),
+            "ä 日本 🎉 ö!\n  Ö ü é"
+        );
+    }
+
+    #[test]
+    fn test_tabs() {
+        let symbol_table = SymbolTable::new(&[("var1", "a"), ("flag", "x")]);
+        assert_eq!(
+            render_text(
+                "func() {\n\t${if flag}\t\n\treturn\t${var1}\n\t${endif}\n}",
+                &symbol_table
+            ),
+            "func() {\n\treturn\ta\n}"
+        );
+    }
+
+    #[test]
+    fn test_macros() {
+        let symbol_table = SymbolTable::new(&[("var1", "a")]);
+        assert_eq!(
+            render_text(
+                "${def item(name, value)}\n- ${name}: ${value} ${var1}\n${enddef}\n${item(one, 1)}\n${def bold(text)}**${text}**${enddef}\n${bold(x)} ${item(two,)}",
+                &symbol_table
+            ),
+            "- one: 1 a\n**x** - two:  a"
+        );
+        let render_error = |text: &str| {
+            let tokens = tokenize_text(text).unwrap();
+            let symbols = parse_tokens(&tokens).unwrap();
+            to_output_tokens(&symbols, &symbol_table).unwrap_err()
+        };
+        assert!(matches!(
+            render_error("${f(x)}"),
+            TextGenError::UndefinedMacro(_)
+        ));
+        // macros are only defined from their definition on
+        assert!(matches!(
+            render_error("${f(x)}\n${def f(x)}${x}${enddef}"),
+            TextGenError::UndefinedMacro(_)
+        ));
+        assert!(matches!(
+            render_error("${def f(x)}${x}${enddef}${f()}"),
+            TextGenError::WrongArgumentCount(_)
+        ));
+        assert!(matches!(
+            render_error("${def f()}${g()}${enddef}${def g()}${f()}${enddef}${f()}"),
+            TextGenError::RecursiveMacro(_)
+        ));
+        // errors of the body are reported at the call
+        assert_eq!(
+            render_error("${def f()}${var2}${enddef}\n${f()}"),
+            TextGenError::VariableNotFound(Range {
+                start_pos: Position { line: 1, column: 0 },
+                end_pos: Position { line: 1, column: 6 }
+            })
+        );
+    }
+
+    #[test]
+    fn test_trailing_whitespace() {
+        let symbol_table = SymbolTable::new(&[("var1", "abc"), ("flag", "x")]);
+        assert_eq!(
+            render_text(
+                "a  \n  \n${if flag}  \n${var1} \t \n${endif} \nb \t",
+                &symbol_table
+            ),
+            "a  \n  \nabc \t \nb \t"
+        );
+    }
+
+    #[test]
+    fn test_block_conditional() {
+        let template =
+            "start\n${if var1}\n  ${var2} and\n${endif}\n${if var2 == b}\n  hidden\n${endif}\nend";
+        let symbol_table = SymbolTable::new(&[("var1", "a"), ("var2", "b")]);
+        assert_eq!(
+            render_text(template, &symbol_table),
+            "start\n  b and\n  hidden\nend"
+        );
+        let symbol_table = SymbolTable::new(&[("var2", "c")]);
+        assert_eq!(render_text(template, &symbol_table), "start\nend");
+        // variables are only needed when the branch is taken
+        let symbol_table = SymbolTable::new::<&str>(&[]);
+        assert_eq!(
+            render_text("${if var1}${var3}${endif}!", &symbol_table),
+            "!"
+        );
+    }
+
+    #[test]
+    fn test_inline_loop() {
+        let symbol_table = SymbolTable::new(&[("items", "a, b,c"), ("empty", "")]);
+        assert_eq!(
+            render_line(
+                "[${for item in items}(${item})${endfor}] [${for item in empty}${item}${endfor}]",
+                &symbol_table
+            ),
+            "[(a)(b)(c)] []"
+        );
+    }
+
+    #[test]
+    fn test_block_loop() {
+        let template =
+            "list:\n${for item in items}\n  - ${item}${if flag} !${endif}\n${endfor}\nend ${flag}";
+        let symbol_table = SymbolTable::new(&[("items", "[\"first\", 2]"), ("flag", "x")]);
+        assert_eq!(
+            render_text(template, &symbol_table),
+            "list:\n  - first !\n  - 2 !\nend x"
+        );
+    }
+
+    #[test]
+    fn test_replace_with_filters() {
+        let symbol_table = SymbolTable::new(&[("var1", " Hello World ")]);
+        assert_eq!(
+            render_line("<${var1|trim|upper}> <${var1|lower}>", &symbol_table),
+            "<HELLO WORLD> < hello world >"
+        );
+    }
+
+    #[test]
+    fn test_replace_with_custom_filter() {
+        let symbol_table = SymbolTable::new(&[("var1", "abc")]);
+        let tokens = create_tokens("${var1|reverse} ${var1|other}".to_string(), 0).unwrap();
+        let symbols = parse_tokens(&tokens).unwrap();
+        let mut options = TextGenOptions::default();
+        options
+            .filters
+            .register("reverse", |value: &str| value.chars().rev().collect());
+        assert!(matches!(
+            to_output_tokens_with_options(&symbols, &symbol_table, &options),
+            Err(TextGenError::UnknownFilter(_))
+        ));
+        options.filters.register("other", |_: &str| "x".to_string());
+        let output_tokens = to_output_tokens_with_options(&symbols, &symbol_table, &options);
+        assert_eq!(reconstruct_text(&output_tokens.unwrap()), "cba x");
+    }
+
+    #[test]
+    fn test_lenient() {
+        let symbol_table = SymbolTable::new(&[("var1", "a"), ("items", "x,y")]);
+        let template =
+            "${var1} ${var2|upper} ${...file}\n${for i in other}\n  ${i} ${var1}\n${endfor}\n\
+                        ${for i in items}${i}${j}${endfor}";
+        let tokens = tokenize_text(template).unwrap();
+        let symbols = parse_tokens(&tokens).unwrap();
+        assert!(matches!(
+            to_output_tokens(&symbols, &symbol_table),
+            Err(TextGenError::VariableNotFound(_))
+        ));
+        let options = TextGenOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let output_tokens = to_output_tokens_with_options(&symbols, &symbol_table, &options);
+        assert_eq!(
+            reconstruct_text(&output_tokens.unwrap()),
+            "a ${var2|upper} ${...file}\n${for i in other}\n  ${i} a\n${endfor}\nx${j}y${j}"
+        );
+    }
+
+    #[test]
+    fn test_expand_values() {
+        let symbol_table = SymbolTable::new(&[
+            ("greeting", "Hello ${name}"),
+            ("name", "${first} ${last|upper}"),
+            ("first", "Jane"),
+            ("last", "doe"),
+            ("a", "${b}"),
+            ("b", "x ${a}"),
+            ("missing", "${other}"),
+        ]);
+        let options = TextGenOptions {
+            expand_values: true,
+            ..Default::default()
+        };
+        let render = |text: &str, options: &TextGenOptions| {
+            let tokens = tokenize_text(text).unwrap();
+            let symbols = parse_tokens(&tokens).unwrap();
+            to_output_tokens_with_options(&symbols, &symbol_table, options)
+                .map(|tokens| reconstruct_text(&tokens))
+        };
+        assert_eq!(
+            render("${greeting}!", &Default::default()).unwrap(),
+            "Hello ${name}!"
+        );
+        assert_eq!(
+            render("${greeting|upper}!", &options).unwrap(),
+            "HELLO JANE DOE!"
+        );
+        assert!(matches!(
+            render("${a}", &options),
+            Err(TextGenError::ExpansionCycle(_))
+        ));
+        // errors in values are reported at the placeholder
+        let err = render("  ${missing}", &options).unwrap_err();
+        assert!(matches!(err, TextGenError::VariableNotFound(_)));
+        assert_eq!(err.range().start_pos.column, 2);
+        let deep: Vec<(String, String)> = (0..=super::MAX_EXPANSION_DEPTH)
+            .map(|i| (format!("v{}", i), format!("${{v{}}}", i + 1)))
+            .collect();
+        let symbol_table = SymbolTable::new(&deep);
+        let tokens = tokenize_text("${v0}").unwrap();
+        let symbols = parse_tokens(&tokens).unwrap();
+        assert!(matches!(
+            to_output_tokens_with_options(&symbols, &symbol_table, &options),
+            Err(TextGenError::ExpansionTooDeep(_))
+        ));
+    }
+
+    #[test]
+    fn test_custom_delimiters() {
+        let symbol_table =
+            SymbolTable::new(&[("var1", "a"), ("file", "./test_corpus/delimiters.txt")]);
+        let options = TextGenOptions {
+            lenient: true,
+            delimiters: Delimiters::new("{{", "}}").unwrap(),
+            ..Default::default()
+        };
+        let template = "${HOME} {{var1}} {{var2}}\n{{for i in items}}\n{{i}}\n{{endfor}}";
+        let tokens = tokenize_text(template).unwrap();
+        let (symbols, errors) = parse_tokens_with_delimiters(&tokens, &options.delimiters);
+        assert!(errors.is_empty());
+        let output_tokens = to_output_tokens_with_options(&symbols, &symbol_table, &options);
+        assert_eq!(
+            reconstruct_text(&output_tokens.unwrap()),
+            "${HOME} a {{var2}}\n{{for i in items}}\n{{i}}\n{{endfor}}"
+        );
+        // spread files use the same delimiters, so `${...}` in them is just text
+        let tokens = tokenize_text("{{...file}}").unwrap();
+        let (symbols, _) = parse_tokens_with_delimiters(&tokens, &options.delimiters);
+        let output_tokens = to_output_tokens_with_options(&symbols, &symbol_table, &options);
+        assert_eq!(reconstruct_text(&output_tokens.unwrap()), "${HOME} a");
+    }
+
+    #[test]
+    fn test_dotted_lookup() {
+        let servers = serde_json::from_str::<serde_json::Value>(
+            r#"[{"name": "a", "port": 80}, {"name": "b", "port": 443}]"#,
+        )
+        .unwrap();
+        let symbol_table = SymbolTable::from_values([
+            ("servers".to_string(), Value::from(servers)),
+            ("config".to_string(), Value::from(r#"{"a": 1}"#)),
+        ]);
+        assert_eq!(
+            render_line(
+                "${servers.0.name} ${for s in servers}[${s.name}:${s.port}]${endfor}",
+                &symbol_table
+            ),
+            "a [a:80][b:443]"
+        );
+        let tokens = create_tokens("${servers.2.name}".to_string(), 0).unwrap();
+        let symbols = parse_tokens(&tokens).unwrap();
+        assert!(matches!(
+            to_output_tokens(&symbols, &symbol_table),
+            Err(TextGenError::VariableNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_spread_file() {
+        let symbol_table = SymbolTable::new(&[("file", "./test_corpus/snippet.txt")]);
+        assert_eq!(
+            render_line("<${...file}>", &symbol_table),
+            "<Lorem ipsum dolor sit amet>"
+        );
+    }
+
+    #[test]
+    fn test_spread_template() {
+        let symbol_table = SymbolTable::new(&[
+            ("name", "world"),
+            ("snippet", "./test_corpus/snippet.txt"),
+            ("template", "./test_corpus/include.txt"),
+        ]);
+        assert_eq!(
+            render_line("${...template}", &symbol_table),
+            "Hello world, Lorem ipsum dolor sit amet"
+        );
+    }
+
+    #[test]
+    fn test_spread_lines() {
+        let symbol_table = SymbolTable::new(&[("file", "./test_corpus/block.yaml")]);
+        assert_eq!(
+            render_text("${...file:2-3}\n${...file:3-}", &symbol_table),
+            "b:\n  c: 2\n  c: 2"
+        );
+        for template in ["${...file:2-4}", "${...file:4-}"] {
+            let tokens = tokenize_text(template).unwrap();
+            let symbols = parse_tokens(&tokens).unwrap();
+            assert!(matches!(
+                to_output_tokens(&symbols, &symbol_table),
+                Err(TextGenError::LineRangeOutOfBounds(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_spread_glob() {
+        let symbol_table = SymbolTable::new(&[
+            ("parts", "./test_corpus/*.yaml"),
+            ("none", "./test_corpus/*.missing"),
+        ]);
+        assert_eq!(
+            render_text("${...parts:1-1}", &symbol_table),
+            "a: 1\ngreeting: Hello world"
+        );
+        let options = TextGenOptions {
+            spread_separator: ", ".to_string(),
+            ..Default::default()
+        };
+        let tokens = tokenize_text("${...parts:1-1}").unwrap();
+        let symbols = parse_tokens(&tokens).unwrap();
+        assert_eq!(
+            reconstruct_text(
+                &to_output_tokens_with_options(&symbols, &symbol_table, &options).unwrap()
+            ),
+            "a: 1, greeting: Hello world"
+        );
+        let tokens = tokenize_text("${...none}").unwrap();
+        let symbols = parse_tokens(&tokens).unwrap();
+        assert!(matches!(
+            to_output_tokens(&symbols, &symbol_table),
+            Err(TextGenError::NoMatchingFiles(_))
+        ));
+    }
+
+    #[test]
+    fn test_spread_multiline() {
+        let symbol_table = SymbolTable::new(&[("file", "./test_corpus/block.yaml")]);
+        let template = "config:\n  ${...file} # end\n\t${...file}\nkey: ${...file}\nlast";
+        assert_eq!(
+            render_text(template, &symbol_table),
+            "config:\n  a: 1\nb:\n  c: 2 # end\n\ta: 1\nb:\n  c: 2\nkey: a: 1\nb:\n  c: 2\nlast"
+        );
+        let options = TextGenOptions {
+            reindent: true,
+            ..Default::default()
+        };
+        let tokens = tokenize_text(template).unwrap();
+        let symbols = parse_tokens(&tokens).unwrap();
+        let output_tokens = to_output_tokens_with_options(&symbols, &symbol_table, &options);
+        assert_eq!(
+            reconstruct_text(&output_tokens.unwrap()),
+            "config:\n  a: 1\n  b:\n    c: 2 # end\n\ta: 1\n\tb:\n\t  c: 2\nkey: a: 1\n     b:\n       c: 2\nlast"
+        );
+    }
+
+    #[test]
+    fn test_multiline_value() {
+        let symbol_table = SymbolTable::new(&[("var1", "a\n\nb")]);
+        assert_eq!(
+            render_text("<${var1}> ${var1}\nend", &symbol_table),
+            "<a\n\nb> a\n\nb\nend"
+        );
+    }
+
+    #[test]
+    fn test_spread_cycle() {
+        let symbol_table = SymbolTable::new(&[("cycle", "./test_corpus/cycle.txt")]);
+        let tokens = create_tokens("${...cycle}".to_string(), 0).unwrap();
+        let symbols = parse_tokens(&tokens).unwrap();
+        assert!(matches!(
+            to_output_tokens(&symbols, &symbol_table),
+            Err(super::TextGenError::IncludeCycle(_))
+        ));
+    }
+
+    #[test]
+    fn test_spread_missing_file() {
+        let symbol_table = SymbolTable::new(&[("file", "./test_corpus/does_not_exist.txt")]);
+        let tokens = create_tokens("${...file}".to_string(), 0).unwrap();
+        let symbols = parse_tokens(&tokens).unwrap();
+        assert!(matches!(
+            to_output_tokens(&symbols, &symbol_table),
+            Err(super::TextGenError::FailedToReadFile(_))
+        ));
+    }
+
+    // Fragments kept in memory, spreading `mem:name`
+    struct MemoryProvider;
+
+    impl ContentProvider for MemoryProvider {
+        fn handles(&self, location: &str) -> bool {
+            location.starts_with("mem:")
+        }
+
+        fn read(&self, location: &str) -> std::io::Result<String> {
+            match location {
+                "mem:greeting" => Ok("Hello ${name}".to_string()),
+                "mem:self" => Ok("${...self}".to_string()),
+                _ => Err(std::io::ErrorKind::NotFound.into()),
+            }
+        }
+    }
+
+    // Counts its reads of `mem:` locations
+    struct CountingProvider(&'static AtomicUsize);
+
+    impl ContentProvider for CountingProvider {
+        fn handles(&self, location: &str) -> bool {
+            location.starts_with("mem:")
+        }
+
+        fn read(&self, location: &str) -> std::io::Result<String> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            MemoryProvider.read(location)
+        }
+    }
+
+    #[test]
+    fn test_caching_provider() {
+        static READS: AtomicUsize = AtomicUsize::new(0);
+        let provider = CachingProvider::new(Box::new(CountingProvider(&READS)));
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    assert_eq!(provider.read("mem:greeting").unwrap(), "Hello ${name}");
+                    assert!(provider.read("mem:missing").is_err());
+                });
+            }
+        });
+        assert_eq!(provider.read("mem:greeting").unwrap(), "Hello ${name}");
+        // failed reads are tried every time, the rest are read once per thread at most
+        assert!((5..=8).contains(&READS.load(Ordering::SeqCst)));
+    }
+
+    #[test]
+    fn test_spread_content_provider() {
+        let mut options = TextGenOptions::default();
+        options
+            .content_providers
+            .insert(0, Box::new(MemoryProvider));
+        let symbol_table = SymbolTable::new(&[
+            ("greeting", "mem:greeting"),
+            ("self", "mem:self"),
+            ("missing", "mem:missing"),
+            ("snippet", "./test_corpus/snippet.txt"),
+            ("name", "world"),
+        ]);
+        let render = |text: &str| {
+            let tokens = tokenize_text(text).unwrap();
+            let symbols = parse_tokens(&tokens).unwrap();
+            to_output_tokens_with_options(&symbols, &symbol_table, &options)
+                .map(|tokens| reconstruct_text(&tokens))
+        };
+        assert_eq!(render("${...greeting}!").unwrap(), "Hello world!");
+        // other locations still come from the file system
+        assert!(render("${...snippet}").is_ok());
+        assert!(matches!(
+            render("${...self}"),
+            Err(TextGenError::IncludeCycle(_))
+        ));
+        assert!(matches!(
+            render("${...missing}"),
+            Err(TextGenError::FailedToReadFile(_))
+        ));
+    }
+
+    #[test]
+    fn test_spread_cache() {
+        static READS: AtomicUsize = AtomicUsize::new(0);
+        let symbol_table = SymbolTable::new(&[("greeting", "mem:greeting"), ("name", "world")]);
+        let tokens = tokenize_text("${...greeting}\n${...greeting}\n${...greeting:1-}").unwrap();
+        let symbols = parse_tokens(&tokens).unwrap();
+        let mut options = TextGenOptions {
+            content_providers: vec![Box::new(CountingProvider(&READS))],
+            ..Default::default()
+        };
+        let output = to_output_tokens_with_options(&symbols, &symbol_table, &options).unwrap();
+        assert_eq!(
+            reconstruct_text(&output),
+            "Hello world\nHello world\nHello world"
+        );
+        // the lines are selected from the file that was read already
+        assert_eq!(READS.load(Ordering::SeqCst), 1);
+        options.cache_spreads = false;
+        to_output_tokens_with_options(&symbols, &symbol_table, &options).unwrap();
+        assert_eq!(READS.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_invalid_offset() {
+        let range = Range {
+            start_pos: Position { line: 1, column: 2 },
+            end_pos: Position { line: 1, column: 4 },
+        };
+        let offset = Offset {
+            line: -2,
+            ..Default::default()
+        };
+        assert_eq!(
+            calculate_new_range(&range, offset),
+            Err(TextGenError::InvalidPosition(Range {
+                start_pos: range.start_pos,
+                end_pos: range.start_pos
+            }))
+        );
+        let offset = Offset {
+            line: 0,
+            column_line: 1,
+            column: -3,
+        };
+        assert!(matches!(
+            calculate_new_range(&range, offset),
+            Err(TextGenError::InvalidPosition(_))
+        ));
+    }
+}
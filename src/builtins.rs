@@ -0,0 +1,199 @@
+use std::cell::OnceCell;
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Variables with these names are defined by ptemplator itself (unless the template's variables
+/// define them), `__now`, `__date` and `__time` take a format (`${__date:%d/%m/%Y}`)
+pub const BUILTIN_VARIABLES: [&str; 5] = ["__now", "__date", "__time", "__uuid", "__hostname"];
+
+pub(crate) fn is_builtin(identifier: &str) -> bool {
+    BUILTIN_VARIABLES.contains(&identifier)
+}
+
+pub(crate) fn takes_format(identifier: &str) -> bool {
+    matches!(identifier, "__now" | "__date" | "__time")
+}
+
+// Specifiers of the formats of time values
+const FORMAT_SPECIFIERS: [char; 9] = ['Y', 'y', 'm', 'd', 'H', 'M', 'S', 's', '%'];
+
+pub(crate) fn is_valid_format(format: &str) -> bool {
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c == '%' && !chars.next().is_some_and(|c| FORMAT_SPECIFIERS.contains(&c)) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Values of the builtin variables during a render. They are computed once, so every use of a
+/// variable in the render gets the same value
+pub(crate) struct Builtins {
+    // seconds since the unix epoch
+    now: i64,
+    uuid: OnceCell<String>,
+    hostname: OnceCell<String>,
+}
+
+impl Builtins {
+    pub(crate) fn new() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs() as i64);
+        Builtins {
+            now,
+            uuid: OnceCell::new(),
+            hostname: OnceCell::new(),
+        }
+    }
+
+    /// Value of the builtin variable `identifier` (`None` if there is no such variable)
+    pub(crate) fn get(&self, identifier: &str, format: Option<&str>) -> Option<String> {
+        match identifier {
+            "__now" => Some(format_time(
+                self.now,
+                format.unwrap_or("%Y-%m-%dT%H:%M:%SZ"),
+            )),
+            "__date" => Some(format_time(self.now, format.unwrap_or("%Y-%m-%d"))),
+            "__time" => Some(format_time(self.now, format.unwrap_or("%H:%M:%S"))),
+            "__uuid" => Some(self.uuid.get_or_init(random_uuid).clone()),
+            "__hostname" => Some(self.hostname.get_or_init(hostname).clone()),
+            _ => None,
+        }
+    }
+}
+
+// `seconds` since the unix epoch (in UTC) written with the strftime like `format`, unknown
+// specifiers are written as they are
+fn format_time(seconds: i64, format: &str) -> String {
+    let (year, month, day) = civil_date(seconds.div_euclid(86400));
+    let time = seconds.rem_euclid(86400);
+    let mut output = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => output.push_str(&year.to_string()),
+            Some('y') => output.push_str(&format!("{:02}", year.rem_euclid(100))),
+            Some('m') => output.push_str(&format!("{:02}", month)),
+            Some('d') => output.push_str(&format!("{:02}", day)),
+            Some('H') => output.push_str(&format!("{:02}", time / 3600)),
+            Some('M') => output.push_str(&format!("{:02}", time / 60 % 60)),
+            Some('S') => output.push_str(&format!("{:02}", time % 60)),
+            Some('s') => output.push_str(&seconds.to_string()),
+            Some('%') => output.push('%'),
+            Some(other) => {
+                output.push('%');
+                output.push(other);
+            }
+            None => output.push('%'),
+        }
+    }
+    output
+}
+
+// (year, month, day) of the day `days` after 1970-01-01 in the proleptic Gregorian calendar
+// (http://howardhinnant.github.io/date_algorithms.html#civil_from_days)
+fn civil_date(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// Version 4 UUID. The randomly seeded hasher of the standard library is used as the source of
+// randomness, which is enough for identifiers
+fn random_uuid() -> String {
+    let random = || {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_nanos()),
+        );
+        hasher.write_u32(process::id());
+        hasher.finish()
+    };
+    let bytes = (u128::from(random()) << 64) | u128::from(random());
+    // version 4 and the RFC 4122 variant
+    let bytes = (bytes & !(0xf << 76) & !(0x3 << 62)) | (0x4 << 76) | (0x2 << 62);
+    let hex = format!("{:032x}", bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn hostname() -> String {
+    ["HOSTNAME", "COMPUTERNAME"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok())
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .or_else(|| {
+            process::Command::new("hostname")
+                .output()
+                .ok()
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+        })
+        .map(|hostname| hostname.trim().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{civil_date, format_time, is_valid_format, Builtins};
+
+    #[test]
+    fn test_format_time() {
+        // 2024-02-29T13:05:09Z
+        let seconds = 1709211909;
+        assert_eq!(
+            format_time(seconds, "%Y-%m-%dT%H:%M:%SZ"),
+            "2024-02-29T13:05:09Z"
+        );
+        assert_eq!(
+            format_time(seconds, "%d/%m/%y 100%% %s"),
+            "29/02/24 100% 1709211909"
+        );
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(-1), (1969, 12, 31));
+        assert!(is_valid_format("%Y%m%d-%H"));
+        assert!(!is_valid_format("%Y-%q"));
+        assert!(!is_valid_format("%"));
+    }
+
+    #[test]
+    fn test_builtins() {
+        let builtins = Builtins::new();
+        let uuid = builtins.get("__uuid", None).unwrap();
+        assert_eq!(uuid.len(), 36);
+        assert_eq!(&uuid[14..15], "4");
+        // the value is the same for the whole render
+        assert_eq!(builtins.get("__uuid", None).unwrap(), uuid);
+        assert_ne!(Builtins::new().get("__uuid", None).unwrap(), uuid);
+        assert_eq!(builtins.get("__date", Some("%Y")).unwrap().len(), 4);
+        assert!(builtins.get("__other", None).is_none());
+    }
+}
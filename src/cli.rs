@@ -0,0 +1,1024 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path;
+
+use ptemplator::{
+    load_vars_file, ChainResolver, Delimiters, EnvResolver, Identifier, LineEnding, SymbolTable,
+    Value, VarsFileError,
+};
+
+use crate::STDIN_PATH;
+
+pub const USAGE: &str = "\
+Usage: ptemplator [render] <template>... [options] [--<VarName> <Var value>...]
+       ptemplator check <template> [options] [--<VarName> <Var value>...]
+       ptemplator vars <template> [options]
+       ptemplator dump <template> [--format json] [options]
+       ptemplator render-dir <src_dir> <out_dir> [options] [--<VarName> <Var value>...]
+       ptemplator completions <bash|zsh|fish>
+
+Commands:
+  render      Render the templates (default), to stdout in the order they are given
+  check       Check the template for errors without rendering it
+  vars        List the variables used in the template (with their position and kind)
+  render-dir  Render every file of <src_dir> into the same path under <out_dir>
+  dump        Write the tokens and symbols of the template (with their ranges) as json
+  completions Write the completion script of the shell, which also completes the variables of
+              the template being rendered (`source <(ptemplator completions bash)`)
+
+Options:
+  --vars-file <path>  Load variables from a json, yaml or toml file
+  --env-fallback      Look up variables that are not defined in the environment
+  --output <path>     Write the output to <path> instead of stdout (render of a single template)
+  --in-place          Overwrite the template with the output (render only)
+  --backup            Keep the original template as <template>.bak (with --in-place)
+  --lenient           Leave placeholders of undefined variables as they are (render, render-dir)
+  --reindent          Indent multi-line values like their placeholder (render, render-dir)
+  --expand            Expand the placeholders in the values of variables too (render, render-dir)
+  --spread-separator <text>
+                      Text written between the files of a glob spread (a newline by default)
+  --no-cache          Read spread files every time they are used instead of once per render, for
+                      files that change while rendering (render, render-dir)
+  --newline <lf|crlf|native>
+                      End the lines of the output with \\n, \\r\\n or the line ending of the
+                      platform instead of like the template (render, render-dir)
+  --stream            Render a line at a time instead of reading the whole template first
+                      (render of a single template, can't be used with --in-place)
+  --watch             Render again whenever the template, a vars file or a spread file changes
+                      (render of a single template, can't be used with --in-place)
+  --interactive       Ask for the values of the variables that are not defined (render of a
+                      single template)
+  --save-vars <path>  Write the answers to a json, yaml or toml vars file (with --interactive)
+  --pattern <glob>    Only render the files matching <glob> (like `*.tmpl`), the rest are copied
+                      as they are (render-dir only)
+  --jobs <n>          Render <n> files at the same time (render, render-dir, all the cores by
+                      default)
+  --open-delim <text> Start tags with <text> instead of `${` (ASCII punctuation, like `{{`)
+  --close-delim <text>
+                      End tags with <text> instead of `}` (ASCII punctuation, like `}}`)
+  --format json       Format of the output of dump (only json for now)
+  --error-format <human|json>
+                      Write errors as text (the default) or as a json object per line with
+                      file, line, column, end_line, end_column, code and message
+  -h, --help          Print this message
+
+The template is read from stdin when <template> is `-`. Variable values starting with `@` are read
+from the file after it (`--cert @cert.pem`, `@-` for stdin) without the final newline, quote them
+to start a value with `@` (`--handle '\"@me\"'`).
+
+Exit codes:
+  0  Success
+  1  Error in the template other than the ones below (unknown filter, include cycle...)
+  2  Invalid arguments (or vars file)
+  3  Syntax error in the template (or in a spread file or value)
+  4  Variable is not defined
+  5  Failed to read or write a file";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Render {
+        templates: Vec<path::PathBuf>,
+        vars: VarArgs,
+        output: Output,
+        lenient: bool,
+        reindent: bool,
+        expand: bool,
+        spread_separator: Option<String>,
+        cache: bool,
+        newline: Option<LineEnding>,
+        stream: bool,
+        watch: bool,
+        prompt: Prompt,
+        // all the cores when not given
+        jobs: Option<usize>,
+        delimiters: Delimiters,
+        error_format: ErrorFormat,
+    },
+    Check {
+        template: path::PathBuf,
+        vars: VarArgs,
+        delimiters: Delimiters,
+        error_format: ErrorFormat,
+    },
+    // Variables are not needed to list the ones used by the template
+    Vars {
+        template: path::PathBuf,
+        delimiters: Delimiters,
+        error_format: ErrorFormat,
+    },
+    // Tokens and symbols as json, for tools (editors, debugging the parser)
+    Dump {
+        template: path::PathBuf,
+        delimiters: Delimiters,
+        error_format: ErrorFormat,
+    },
+    RenderDir {
+        src_dir: path::PathBuf,
+        out_dir: path::PathBuf,
+        vars: VarArgs,
+        pattern: Option<String>,
+        lenient: bool,
+        reindent: bool,
+        expand: bool,
+        spread_separator: Option<String>,
+        cache: bool,
+        newline: Option<LineEnding>,
+        // all the cores when not given
+        jobs: Option<usize>,
+        delimiters: Delimiters,
+        error_format: ErrorFormat,
+    },
+    Completions(Shell),
+    Help,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum ErrorFormat {
+    #[default]
+    Human,
+    Json,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Output {
+    Stdout,
+    File(path::PathBuf),
+    // Overwrite the template, optionally keeping the original as `<template>.bak`
+    InPlace { backup: bool },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Prompt {
+    Never,
+    // Ask for the variables that are missing, optionally saving the answers to a vars file
+    Missing { save_vars: Option<path::PathBuf> },
+}
+
+#[derive(Debug, PartialEq, Default)]
+pub struct VarArgs {
+    variables: Vec<(Identifier, String)>,
+    vars_files: Vec<path::PathBuf>,
+    env_fallback: bool,
+}
+
+impl VarArgs {
+    pub fn vars_files(&self) -> &[path::PathBuf] {
+        &self.vars_files
+    }
+
+    /// Variables given with `--var` override the ones from vars files (later files override earlier
+    /// ones), which override the environment with `--env-fallback`
+    pub fn symbol_table(&self) -> Result<SymbolTable, CliError> {
+        let mut file_variables = HashMap::new();
+        for file_path in &self.vars_files {
+            let variables = load_vars_file(file_path)
+                .map_err(|err| CliError::InvalidVarsFile(file_path.clone(), err))?;
+            file_variables.extend(variables);
+        }
+        let variables: HashMap<Identifier, Value> = self
+            .variables
+            .iter()
+            .map(|(identifier, value)| (identifier.clone(), Value::from(value.as_str())))
+            .collect();
+        let resolver = ChainResolver::new().with(variables).with(file_variables);
+        if self.env_fallback {
+            Ok(SymbolTable::from_resolver(resolver.with(EnvResolver)))
+        } else {
+            Ok(SymbolTable::from_resolver(resolver))
+        }
+    }
+}
+
+pub enum CliError {
+    MissingTemplate,
+    MissingOutputDir,
+    SeveralTemplates(&'static str),
+    MissingValue(String),
+    UnexpectedArgument(String),
+    InvalidVariableName(String),
+    UnterminatedValue(String),
+    ConflictingFlags(&'static str, &'static str),
+    InvalidVarsFile(path::PathBuf, VarsFileError),
+    InvalidDelimiters(String, String),
+    InvalidFlagValue(&'static str, String),
+    FailedToReadValue(path::PathBuf, String),
+    StdinReadTwice,
+    MissingShell,
+    UnknownShell(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingTemplate => write!(f, "Template path is missing"),
+            Self::MissingOutputDir => write!(f, "Output directory is missing"),
+            Self::SeveralTemplates(flag) => {
+                write!(f, "{} can only be used with a single template", flag)
+            }
+            Self::MissingValue(flag) => write!(f, "Value is missing for {}", flag),
+            Self::UnexpectedArgument(arg) => write!(f, "Unexpected argument {}", arg),
+            Self::InvalidVariableName(name) => write!(
+                f,
+                "Invalid variable name {}: use --<VarName> <Var value>",
+                name
+            ),
+            Self::UnterminatedValue(value) => write!(f, "Variable value not terminated {}", value),
+            Self::ConflictingFlags(first, second) => {
+                write!(f, "{} can't be used with {}", first, second)
+            }
+            Self::InvalidVarsFile(file_path, err) => {
+                write!(f, "{}: {}", file_path.display(), err)
+            }
+            Self::InvalidDelimiters(open, close) => write!(
+                f,
+                "Invalid delimiters {} {}: use ASCII punctuation other than \\",
+                open, close
+            ),
+            Self::InvalidFlagValue(flag, value) => {
+                write!(f, "Invalid value {} for {}", value, flag)
+            }
+            Self::FailedToReadValue(file_path, err) => {
+                write!(
+                    f,
+                    "Failed to read value from {}: {}",
+                    file_path.display(),
+                    err
+                )
+            }
+            Self::StdinReadTwice => write!(
+                f,
+                "stdin can only be read once (by a single template or @- value)"
+            ),
+            Self::MissingShell => write!(f, "Shell is missing: use bash, zsh or fish"),
+            Self::UnknownShell(shell) => {
+                write!(f, "Unknown shell {}: use bash, zsh or fish", shell)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+const VARS_FILE_FLAG: &str = "--vars-file";
+const ENV_FALLBACK_FLAG: &str = "--env-fallback";
+const OUTPUT_FLAG: &str = "--output";
+const IN_PLACE_FLAG: &str = "--in-place";
+const BACKUP_FLAG: &str = "--backup";
+const LENIENT_FLAG: &str = "--lenient";
+const REINDENT_FLAG: &str = "--reindent";
+const EXPAND_FLAG: &str = "--expand";
+const NO_CACHE_FLAG: &str = "--no-cache";
+const NEWLINE_FLAG: &str = "--newline";
+const STREAM_FLAG: &str = "--stream";
+const PATTERN_FLAG: &str = "--pattern";
+const JOBS_FLAG: &str = "--jobs";
+const SPREAD_SEPARATOR_FLAG: &str = "--spread-separator";
+const WATCH_FLAG: &str = "--watch";
+const INTERACTIVE_FLAG: &str = "--interactive";
+const SAVE_VARS_FLAG: &str = "--save-vars";
+const OPEN_DELIM_FLAG: &str = "--open-delim";
+const CLOSE_DELIM_FLAG: &str = "--close-delim";
+const ERROR_FORMAT_FLAG: &str = "--error-format";
+const FORMAT_FLAG: &str = "--format";
+
+/// Value an option takes
+#[derive(Debug, PartialEq)]
+pub enum FlagValue {
+    None,
+    Path,
+    Text,
+    Choice(&'static [&'static str]),
+}
+
+/// Every option of the commands, for the completion scripts
+pub const FLAGS: [(&str, FlagValue); 22] = [
+    (VARS_FILE_FLAG, FlagValue::Path),
+    (ENV_FALLBACK_FLAG, FlagValue::None),
+    (OUTPUT_FLAG, FlagValue::Path),
+    (IN_PLACE_FLAG, FlagValue::None),
+    (BACKUP_FLAG, FlagValue::None),
+    (LENIENT_FLAG, FlagValue::None),
+    (REINDENT_FLAG, FlagValue::None),
+    (EXPAND_FLAG, FlagValue::None),
+    (SPREAD_SEPARATOR_FLAG, FlagValue::Text),
+    (NO_CACHE_FLAG, FlagValue::None),
+    (NEWLINE_FLAG, FlagValue::Choice(&["lf", "crlf", "native"])),
+    (STREAM_FLAG, FlagValue::None),
+    (WATCH_FLAG, FlagValue::None),
+    (INTERACTIVE_FLAG, FlagValue::None),
+    (SAVE_VARS_FLAG, FlagValue::Path),
+    (PATTERN_FLAG, FlagValue::Text),
+    (JOBS_FLAG, FlagValue::Text),
+    (OPEN_DELIM_FLAG, FlagValue::Text),
+    (CLOSE_DELIM_FLAG, FlagValue::Text),
+    (FORMAT_FLAG, FlagValue::Choice(&["json"])),
+    (ERROR_FORMAT_FLAG, FlagValue::Choice(&["human", "json"])),
+    ("--help", FlagValue::None),
+];
+
+/// Names of the commands
+pub const COMMANDS: [&str; 6] = [
+    "render",
+    "check",
+    "vars",
+    "dump",
+    "render-dir",
+    "completions",
+];
+
+/// Parse the arguments (without the program name)
+pub fn parse_args(args: &[String]) -> Result<Command, CliError> {
+    let (command, args) = match args.first().map(String::as_str) {
+        Some("render") | Some("check") | Some("vars") | Some("dump") | Some("render-dir") => {
+            (args[0].as_str(), &args[1..])
+        }
+        Some("help") => return Ok(Command::Help),
+        Some("completions") => {
+            let shell = match args.get(1).map(String::as_str) {
+                Some("bash") => Shell::Bash,
+                Some("zsh") => Shell::Zsh,
+                Some("fish") => Shell::Fish,
+                Some(other) => return Err(CliError::UnknownShell(other.to_string())),
+                None => return Err(CliError::MissingShell),
+            };
+            return match args.get(2) {
+                Some(arg) => Err(CliError::UnexpectedArgument(arg.to_string())),
+                None => Ok(Command::Completions(shell)),
+            };
+        }
+        _ => ("render", args),
+    };
+    let mut templates = vec![];
+    let mut out_dir = None;
+    let mut pattern = None;
+    let mut jobs = None;
+    let mut vars = VarArgs::default();
+    let mut output_path = None;
+    let mut in_place = false;
+    let mut backup = false;
+    let mut lenient = false;
+    let mut reindent = false;
+    let mut expand = false;
+    let mut cache = true;
+    let mut newline = None;
+    let mut spread_separator = None;
+    let mut stream = false;
+    let mut watch = false;
+    let mut interactive = false;
+    let mut save_vars = None;
+    let mut open_delim = DEFAULT_OPEN_DELIM;
+    let mut close_delim = DEFAULT_CLOSE_DELIM;
+    let mut error_format = ErrorFormat::default();
+    // `@<path>` values are read once every argument is known (stdin may be the template)
+    let mut variables = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            VARS_FILE_FLAG => vars
+                .vars_files
+                .push(path::PathBuf::from(flag_value(arg, args.next())?)),
+            ENV_FALLBACK_FLAG => vars.env_fallback = true,
+            OUTPUT_FLAG | IN_PLACE_FLAG | BACKUP_FLAG | STREAM_FLAG | WATCH_FLAG
+            | INTERACTIVE_FLAG | SAVE_VARS_FLAG
+                if command != "render" =>
+            {
+                return Err(CliError::UnexpectedArgument(arg.to_string()))
+            }
+            LENIENT_FLAG
+            | REINDENT_FLAG
+            | EXPAND_FLAG
+            | SPREAD_SEPARATOR_FLAG
+            | NO_CACHE_FLAG
+            | NEWLINE_FLAG
+                if command != "render" && command != "render-dir" =>
+            {
+                return Err(CliError::UnexpectedArgument(arg.to_string()))
+            }
+            PATTERN_FLAG if command != "render-dir" => {
+                return Err(CliError::UnexpectedArgument(arg.to_string()))
+            }
+            JOBS_FLAG if command != "render" && command != "render-dir" => {
+                return Err(CliError::UnexpectedArgument(arg.to_string()))
+            }
+            PATTERN_FLAG => pattern = Some(flag_value(arg, args.next())?.to_string()),
+            JOBS_FLAG => {
+                let value = flag_value(arg, args.next())?;
+                jobs = match value.parse::<usize>() {
+                    Ok(jobs) if jobs > 0 => Some(jobs),
+                    _ => return Err(CliError::InvalidFlagValue(JOBS_FLAG, value.to_string())),
+                }
+            }
+            OUTPUT_FLAG => output_path = Some(path::PathBuf::from(flag_value(arg, args.next())?)),
+            IN_PLACE_FLAG => in_place = true,
+            BACKUP_FLAG => backup = true,
+            LENIENT_FLAG => lenient = true,
+            REINDENT_FLAG => reindent = true,
+            EXPAND_FLAG => expand = true,
+            NO_CACHE_FLAG => cache = false,
+            NEWLINE_FLAG => {
+                newline = match flag_value(arg, args.next())? {
+                    "lf" => Some(LineEnding::Lf),
+                    "crlf" => Some(LineEnding::Crlf),
+                    "native" => Some(LineEnding::native()),
+                    other => {
+                        return Err(CliError::InvalidFlagValue(NEWLINE_FLAG, other.to_string()))
+                    }
+                }
+            }
+            SPREAD_SEPARATOR_FLAG => {
+                spread_separator = Some(flag_value(arg, args.next())?.to_string())
+            }
+            STREAM_FLAG => stream = true,
+            WATCH_FLAG => watch = true,
+            INTERACTIVE_FLAG => interactive = true,
+            SAVE_VARS_FLAG => save_vars = Some(path::PathBuf::from(flag_value(arg, args.next())?)),
+            OPEN_DELIM_FLAG => open_delim = flag_value(arg, args.next())?,
+            CLOSE_DELIM_FLAG => close_delim = flag_value(arg, args.next())?,
+            ERROR_FORMAT_FLAG => {
+                let value = flag_value(arg, args.next())?;
+                error_format = parse_error_format(value).ok_or_else(|| {
+                    CliError::InvalidFlagValue(ERROR_FORMAT_FLAG, value.to_string())
+                })?
+            }
+            // other commands take `--format` as a variable
+            FORMAT_FLAG if command == "dump" => match flag_value(arg, args.next())? {
+                "json" => {}
+                other => return Err(CliError::InvalidFlagValue(FORMAT_FLAG, other.to_string())),
+            },
+            flag if flag.starts_with("--") => {
+                let (name, value) = match flag[2..].split_once('=') {
+                    Some((name, value)) => (name, value),
+                    None => (&flag[2..], flag_value(arg, args.next())?),
+                };
+                variables.push((parse_identifier(name)?, parse_variable(value)?));
+            }
+            _ if templates.is_empty() || command == "render" => {
+                templates.push(path::PathBuf::from(arg))
+            }
+            _ if command == "render-dir" && out_dir.is_none() => {
+                out_dir = Some(path::PathBuf::from(arg))
+            }
+            _ => return Err(CliError::UnexpectedArgument(arg.to_string())),
+        }
+    }
+    if templates.is_empty() {
+        return Err(CliError::MissingTemplate);
+    }
+    if templates.len() > 1 {
+        // a single output file, and the rest only follow a single template
+        let flag = if output_path.is_some() {
+            Some(OUTPUT_FLAG)
+        } else if stream {
+            Some(STREAM_FLAG)
+        } else if watch {
+            Some(WATCH_FLAG)
+        } else {
+            interactive.then_some(INTERACTIVE_FLAG)
+        };
+        if let Some(flag) = flag {
+            return Err(CliError::SeveralTemplates(flag));
+        }
+    }
+    let template = templates[0].clone();
+    let stdin_reads = variables
+        .iter()
+        .filter(|(_, value)| matches!(value, VarValue::File(path) if path == STDIN_PATH))
+        .count()
+        + templates
+            .iter()
+            .filter(|template| *template == path::Path::new(STDIN_PATH))
+            .count();
+    if stdin_reads > 1 {
+        return Err(CliError::StdinReadTwice);
+    }
+    for (identifier, value) in variables {
+        vars.variables.push((identifier, value.read()?));
+    }
+    let delimiters = Delimiters::new(open_delim, close_delim).ok_or_else(|| {
+        CliError::InvalidDelimiters(open_delim.to_string(), close_delim.to_string())
+    })?;
+    match command {
+        "check" => Ok(Command::Check {
+            template,
+            vars,
+            delimiters,
+            error_format,
+        }),
+        "vars" => Ok(Command::Vars {
+            template,
+            delimiters,
+            error_format,
+        }),
+        "dump" => Ok(Command::Dump {
+            template,
+            delimiters,
+            error_format,
+        }),
+        "render-dir" => Ok(Command::RenderDir {
+            src_dir: template,
+            out_dir: out_dir.ok_or(CliError::MissingOutputDir)?,
+            vars,
+            pattern,
+            lenient,
+            reindent,
+            expand,
+            spread_separator,
+            cache,
+            newline,
+            jobs,
+            delimiters,
+            error_format,
+        }),
+        _ => {
+            let output = match (output_path, in_place) {
+                (Some(_), true) => Err(CliError::ConflictingFlags(OUTPUT_FLAG, IN_PLACE_FLAG)),
+                // the template can't be overwritten while it's being read
+                (_, true) if stream => Err(CliError::ConflictingFlags(STREAM_FLAG, IN_PLACE_FLAG)),
+                // the output would be seen as a change of the template
+                (_, true) if watch => Err(CliError::ConflictingFlags(WATCH_FLAG, IN_PLACE_FLAG)),
+                (_, false) if backup => Err(CliError::UnexpectedArgument(BACKUP_FLAG.to_string())),
+                (Some(path), false) => Ok(Output::File(path)),
+                (None, true) => Ok(Output::InPlace { backup }),
+                (None, false) => Ok(Output::Stdout),
+            }?;
+            let prompt = match (interactive, save_vars) {
+                // the offending lines are needed to show where the variables are used
+                (true, _) if stream => {
+                    Err(CliError::ConflictingFlags(STREAM_FLAG, INTERACTIVE_FLAG))
+                }
+                (true, save_vars) => Ok(Prompt::Missing { save_vars }),
+                (false, Some(_)) => Err(CliError::UnexpectedArgument(SAVE_VARS_FLAG.to_string())),
+                (false, None) => Ok(Prompt::Never),
+            }?;
+            Ok(Command::Render {
+                templates,
+                vars,
+                output,
+                lenient,
+                reindent,
+                expand,
+                spread_separator,
+                cache,
+                newline,
+                stream,
+                watch,
+                prompt,
+                jobs,
+                delimiters,
+                error_format,
+            })
+        }
+    }
+}
+
+/// Format of the errors asked for by the arguments, even when they can't be parsed (the default
+/// when it's not given or not valid)
+pub fn error_format(args: &[String]) -> ErrorFormat {
+    args.windows(2)
+        .filter(|pair| pair[0] == ERROR_FORMAT_FLAG)
+        .filter_map(|pair| parse_error_format(&pair[1]))
+        .next_back()
+        .unwrap_or_default()
+}
+
+fn parse_error_format(value: &str) -> Option<ErrorFormat> {
+    match value {
+        "human" => Some(ErrorFormat::Human),
+        "json" => Some(ErrorFormat::Json),
+        _ => None,
+    }
+}
+
+// Same as `Delimiters::default`
+const DEFAULT_OPEN_DELIM: &str = "${";
+const DEFAULT_CLOSE_DELIM: &str = "}";
+
+fn flag_value<'a>(flag: &str, value: Option<&'a String>) -> Result<&'a str, CliError> {
+    value
+        .map(String::as_str)
+        .ok_or(CliError::MissingValue(flag.to_string()))
+}
+
+// Same characters as the words in templates (plus `_`)
+fn parse_identifier(name: &str) -> Result<Identifier, CliError> {
+    let is_valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| !c.is_whitespace() && (c == '_' || !c.is_ascii_punctuation()));
+    if is_valid {
+        Ok(name.to_string())
+    } else {
+        Err(CliError::InvalidVariableName(name.to_string()))
+    }
+}
+
+// Value of a variable as given on the command line
+enum VarValue {
+    Text(String),
+    // `@<path>`, read from the file (stdin for `-`)
+    File(path::PathBuf),
+}
+
+impl VarValue {
+    // The final newline is left out, like `$(cat file)` does
+    fn read(self) -> Result<String, CliError> {
+        let file_path = match self {
+            VarValue::Text(text) => return Ok(text),
+            VarValue::File(file_path) => file_path,
+        };
+        let content = if file_path == path::Path::new(STDIN_PATH) {
+            let mut content = String::new();
+            io::stdin().read_to_string(&mut content).map(|_| content)
+        } else {
+            fs::read_to_string(&file_path)
+        }
+        .map_err(|err| CliError::FailedToReadValue(file_path.clone(), err.to_string()))?;
+        let content = content.strip_suffix('\n').unwrap_or(&content);
+        Ok(content.strip_suffix('\r').unwrap_or(content).to_string())
+    }
+}
+
+fn parse_variable(value: &str) -> Result<VarValue, CliError> {
+    if value.starts_with('"') {
+        if value.len() < 2 || !value.ends_with('"') {
+            return Err(CliError::UnterminatedValue(value.to_string()));
+        }
+        Ok(VarValue::Text(value[1..(value.len() - 1)].to_string()))
+    } else if let Some(file_path) = value.strip_prefix('@') {
+        Ok(VarValue::File(path::PathBuf::from(file_path)))
+    } else {
+        Ok(VarValue::Text(value.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path;
+
+    use super::{
+        error_format, parse_args, CliError, Command, ErrorFormat, Output, Prompt, Shell, VarArgs,
+        FLAGS, USAGE,
+    };
+    use ptemplator::{Delimiters, LineEnding};
+
+    fn to_args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|each| each.to_string()).collect()
+    }
+
+    fn render_vars(args: &[&str]) -> VarArgs {
+        match parse_args(&to_args(args)) {
+            Ok(Command::Render { vars, .. }) => vars,
+            _ => panic!("Expected a render command"),
+        }
+    }
+
+    #[test]
+    fn test_arg_parsing() {
+        let symbols = render_vars(&["t.txt", "--var1", "1", "--var2", "\"2\""])
+            .symbol_table()
+            .unwrap();
+        assert_eq!(symbols.get_variable("var2").unwrap(), "2".to_string());
+        let symbols = render_vars(&["t.txt", "--var1=a b"])
+            .symbol_table()
+            .unwrap();
+        assert_eq!(symbols.get_variable("var1").unwrap(), "a b".to_string());
+    }
+
+    #[test]
+    fn test_values_from_files() {
+        let symbols = render_vars(&[
+            "t.txt",
+            "--snippet",
+            "@./test_corpus/snippet.txt",
+            "--handle",
+            "\"@me\"",
+        ])
+        .symbol_table()
+        .unwrap();
+        assert_eq!(
+            symbols.get_variable("snippet").unwrap(),
+            fs::read_to_string("./test_corpus/snippet.txt")
+                .unwrap()
+                .trim_end_matches('\n')
+        );
+        assert_eq!(symbols.get_variable("handle").unwrap(), "@me");
+        assert!(matches!(
+            parse_args(&to_args(&["t.txt", "--var1", "@./test_corpus/missing.txt"])),
+            Err(CliError::FailedToReadValue(_, _))
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["-", "--var1", "@-"])),
+            Err(CliError::StdinReadTwice)
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["-", "t.txt", "-"])),
+            Err(CliError::StdinReadTwice)
+        ));
+    }
+
+    #[test]
+    fn test_vars_file_overridden_by_args() {
+        let symbols = render_vars(&[
+            "t.txt",
+            "--greeting",
+            "Hi",
+            "--vars-file",
+            "./test_corpus/vars.json",
+        ])
+        .symbol_table()
+        .unwrap();
+        assert_eq!(symbols.get_variable("greeting").unwrap(), "Hi".to_string());
+        assert_eq!(symbols.get_variable("count").unwrap(), "3".to_string());
+    }
+
+    #[test]
+    fn test_env_fallback_flag() {
+        std::env::set_var("PTEMPLATOR_TEST_CLI_ENV_VAR", "from env");
+        let symbols = render_vars(&["t.txt", "--var1", "1", "--env-fallback"])
+            .symbol_table()
+            .unwrap();
+        assert_eq!(symbols.get_variable("var1").unwrap(), "1".to_string());
+        assert_eq!(
+            symbols.get_variable("PTEMPLATOR_TEST_CLI_ENV_VAR").unwrap(),
+            "from env".to_string()
+        );
+    }
+
+    #[test]
+    fn test_output_args() {
+        let command = parse_args(&to_args(&["render", "t.txt", "--output", "out.txt"]));
+        assert!(matches!(
+            command,
+            Ok(Command::Render { output: Output::File(path), .. }) if path == path::Path::new("out.txt")
+        ));
+        let command = parse_args(&to_args(&["--backup", "t.txt", "--in-place"]));
+        assert!(matches!(
+            command,
+            Ok(Command::Render {
+                output: Output::InPlace { backup: true },
+                ..
+            })
+        ));
+        let command = parse_args(&to_args(&["t.txt", "--in-place", "--output", "out.txt"]));
+        assert!(matches!(command, Err(CliError::ConflictingFlags(_, _))));
+        let command = parse_args(&to_args(&["t.txt", "--stream", "--output", "out.txt"]));
+        assert!(matches!(
+            command,
+            Ok(Command::Render {
+                stream: true,
+                output: Output::File(_),
+                ..
+            })
+        ));
+        let command = parse_args(&to_args(&["t.txt", "--stream", "--in-place"]));
+        assert!(matches!(command, Err(CliError::ConflictingFlags(_, _))));
+        let command = parse_args(&to_args(&[
+            "t.txt",
+            "--interactive",
+            "--save-vars",
+            "v.json",
+        ]));
+        assert!(matches!(
+            command,
+            Ok(Command::Render { prompt: Prompt::Missing { save_vars: Some(path) }, .. })
+                if path == path::Path::new("v.json")
+        ));
+        let command = parse_args(&to_args(&["t.txt", "--save-vars", "v.json"]));
+        assert!(matches!(command, Err(CliError::UnexpectedArgument(_))));
+        let command = parse_args(&to_args(&["t.txt", "--watch", "--in-place"]));
+        assert!(matches!(command, Err(CliError::ConflictingFlags(_, _))));
+        let command = parse_args(&to_args(&["t.txt", "--watch", "--output", "out.txt"]));
+        assert!(matches!(command, Ok(Command::Render { watch: true, .. })));
+    }
+
+    #[test]
+    fn test_subcommands() {
+        assert!(matches!(
+            parse_args(&to_args(&["check", "-", "--var1", "1"])),
+            Ok(Command::Check { template, .. }) if template == path::Path::new("-")
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["vars", "t.txt"])),
+            Ok(Command::Vars { .. })
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["vars", "t.txt", "--in-place"])),
+            Err(CliError::UnexpectedArgument(_))
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["t.txt", "--lenient"])),
+            Ok(Command::Render { lenient: true, .. })
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["t.txt", "--reindent"])),
+            Ok(Command::Render {
+                reindent: true,
+                lenient: false,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["check", "t.txt", "--lenient"])),
+            Err(CliError::UnexpectedArgument(_))
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["t.txt", "--expand"])),
+            Ok(Command::Render { expand: true, .. })
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["check", "t.txt", "--expand"])),
+            Err(CliError::UnexpectedArgument(_))
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["render-dir", "src", "out", "--pattern", "*.tmpl", "--lenient"])),
+            Ok(Command::RenderDir { out_dir, pattern: Some(pattern), lenient: true, .. })
+                if out_dir == path::Path::new("out") && pattern == "*.tmpl"
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["render-dir", "src", "out", "--jobs", "8"])),
+            Ok(Command::RenderDir {
+                jobs: Some(8),
+                cache: true,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["t.txt", "--no-cache"])),
+            Ok(Command::Render {
+                cache: false,
+                newline: None,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["render-dir", "src", "out", "--newline", "crlf"])),
+            Ok(Command::RenderDir {
+                newline: Some(LineEnding::Crlf),
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["t.txt", "--newline", "cr"])),
+            Err(CliError::InvalidFlagValue(_, _))
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["render-dir", "src", "out", "--jobs", "0"])),
+            Err(CliError::InvalidFlagValue(_, _))
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["a.txt", "--jobs", "2", "b.txt", "--in-place"])),
+            Ok(Command::Render { templates, jobs: Some(2), .. }) if templates.len() == 2
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["a.txt", "b.txt", "--output", "out.txt"])),
+            Err(CliError::SeveralTemplates(_))
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["a.txt", "--stream", "b.txt"])),
+            Err(CliError::SeveralTemplates(_))
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["check", "a.txt", "b.txt"])),
+            Err(CliError::UnexpectedArgument(_))
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["vars", "a.txt", "--jobs", "2"])),
+            Err(CliError::UnexpectedArgument(_))
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["t.txt", "--spread-separator", ", "])),
+            Ok(Command::Render { spread_separator: Some(separator), .. }) if separator == ", "
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["render-dir", "src"])),
+            Err(CliError::MissingOutputDir)
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["render-dir", "src", "out", "--in-place"])),
+            Err(CliError::UnexpectedArgument(_))
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["t.txt", "--pattern", "*"])),
+            Err(CliError::UnexpectedArgument(_))
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["t.txt", "--help"])),
+            Ok(Command::Help)
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["vars", "t.txt", "--open-delim", "{{", "--close-delim", "}}"])),
+            Ok(Command::Vars { delimiters, .. }) if delimiters == Delimiters::new("{{", "}}").unwrap()
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["check", "t.txt"])),
+            Ok(Command::Check { delimiters, error_format: ErrorFormat::Human, .. })
+                if delimiters == Delimiters::default()
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["check", "t.txt", "--error-format", "json"])),
+            Ok(Command::Check {
+                error_format: ErrorFormat::Json,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["dump", "t.txt", "--format", "json"])),
+            Ok(Command::Dump { template, .. }) if template == path::Path::new("t.txt")
+        ));
+    }
+
+    #[test]
+    fn test_completions() {
+        assert!(matches!(
+            parse_args(&to_args(&["completions", "zsh"])),
+            Ok(Command::Completions(Shell::Zsh))
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["completions"])),
+            Err(CliError::MissingShell)
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["completions", "tcsh"])),
+            Err(CliError::UnknownShell(_))
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["completions", "bash", "t.txt"])),
+            Err(CliError::UnexpectedArgument(_))
+        ));
+        // the options that are completed are the ones in the usage
+        let usage_flags: Vec<&str> = USAGE
+            .lines()
+            .filter(|line| line.starts_with("  -"))
+            .filter_map(|line| line.split_whitespace().find(|word| word.starts_with("--")))
+            .collect();
+        for (flag, _) in FLAGS {
+            assert!(usage_flags.contains(&flag), "{}", flag);
+        }
+        for flag in usage_flags {
+            assert!(FLAGS.iter().any(|(each, _)| *each == flag), "{}", flag);
+        }
+    }
+
+    #[test]
+    fn test_error_format() {
+        assert_eq!(
+            error_format(&to_args(&["t.txt", "--error-format", "json", "--bad flag"])),
+            ErrorFormat::Json
+        );
+        assert_eq!(
+            error_format(&to_args(&["t.txt", "--error-format", "xml"])),
+            ErrorFormat::Human
+        );
+    }
+
+    #[test]
+    fn test_invalid_args() {
+        assert!(matches!(
+            parse_args(&to_args(&[])),
+            Err(CliError::MissingTemplate)
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["t.txt", "--var1"])),
+            Err(CliError::MissingValue(_))
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["t.txt", "--var 1", "2"])),
+            Err(CliError::InvalidVariableName(_))
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["t.txt", "--var1", "\"2"])),
+            Err(CliError::UnterminatedValue(_))
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["dump", "t.txt", "other.txt"])),
+            Err(CliError::UnexpectedArgument(_))
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["t.txt", "--open-delim", "<<a"])),
+            Err(CliError::InvalidDelimiters(_, _))
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["t.txt", "--error-format", "xml"])),
+            Err(CliError::InvalidFlagValue(_, _))
+        ));
+        assert!(matches!(
+            parse_args(&to_args(&["dump", "t.txt", "--format", "yaml"])),
+            Err(CliError::InvalidFlagValue(_, _))
+        ));
+    }
+}
@@ -0,0 +1,182 @@
+use crate::cli::{FlagValue, Shell, COMMANDS, FLAGS};
+
+// The variables of the template are listed by `ptemplator vars` (the roots of dotted identifiers,
+// the way they are given as options)
+const LIST_VARS: &str =
+    "ptemplator vars \"$template\" 2>/dev/null | cut -d' ' -f3 | cut -d. -f1 | sort -u | sed 's/^/--/'";
+
+const BASH: &str = r#"# completion of ptemplator, the variables of the template are completed as --<VarName> options
+_ptemplator() {
+    local cur="${COMP_WORDS[COMP_CWORD]}" prev="${COMP_WORDS[COMP_CWORD-1]}"
+    local i word template vars
+    case "$prev" in
+        @PATH_FLAGS@) COMPREPLY=($(compgen -f -- "$cur")); return ;;
+@CHOICES@
+        @TEXT_FLAGS@) return ;;
+        completions) COMPREPLY=($(compgen -W "bash zsh fish" -- "$cur")); return ;;
+    esac
+    if [[ "$cur" == -* ]]; then
+        # the template is the first file that is not the value of an option
+        for ((i = 1; i < COMP_CWORD; i++)); do
+            word="${COMP_WORDS[i]}"
+            case "${COMP_WORDS[i-1]}" in
+                @VALUE_FLAGS@) continue ;;
+            esac
+            if [[ "$word" != -* && -f "$word" ]]; then
+                template="$word"
+                break
+            fi
+        done
+        if [[ -n "$template" ]]; then
+            vars="$(@LIST_VARS@)"
+        fi
+        COMPREPLY=($(compgen -W "@FLAGS@ $vars" -- "$cur"))
+    elif [[ "$COMP_CWORD" -eq 1 ]]; then
+        COMPREPLY=($(compgen -W "@COMMANDS@" -- "$cur") $(compgen -f -- "$cur"))
+    else
+        COMPREPLY=($(compgen -f -- "$cur"))
+    fi
+}
+complete -o filenames -F _ptemplator ptemplator
+"#;
+
+const ZSH: &str = r#"#compdef ptemplator
+# completion of ptemplator, the variables of the template are completed as --<VarName> options
+_ptemplator() {
+    local i word template
+    local -a vars
+    case "${words[CURRENT-1]}" in
+        @PATH_FLAGS@) _files; return ;;
+@CHOICES@
+        @TEXT_FLAGS@) return ;;
+        completions) compadd -- bash zsh fish; return ;;
+    esac
+    if [[ "$PREFIX" == -* ]]; then
+        # the template is the first file that is not the value of an option
+        for ((i = 2; i < CURRENT; i++)); do
+            word="${words[i]}"
+            case "${words[i-1]}" in
+                @VALUE_FLAGS@) continue ;;
+            esac
+            if [[ "$word" != -* && -f "$word" ]]; then
+                template="$word"
+                break
+            fi
+        done
+        if [[ -n "$template" ]]; then
+            vars=(${(f)"$(@LIST_VARS@)"})
+        fi
+        compadd -- @FLAGS@ $vars
+    elif (( CURRENT == 2 )); then
+        compadd -- @COMMANDS@
+        _files
+    else
+        _files
+    fi
+}
+compdef _ptemplator ptemplator
+"#;
+
+const FISH: &str = r#"# completion of ptemplator, the variables of the template are completed as --<VarName> options
+function __ptemplator_vars
+    set -l previous ''
+    # the template is the first file that is not the value of an option
+    for template in (commandline -opc)[2..-1]
+        if not contains -- $previous @VALUE_FLAGS@; and not string match -q -- '-*' $template; and test -f $template
+            @LIST_VARS@
+            return
+        end
+        set previous $template
+    end
+end
+
+complete -c ptemplator -f
+complete -c ptemplator -n __fish_use_subcommand -a '@COMMANDS@'
+complete -c ptemplator -n '__fish_seen_subcommand_from completions' -a 'bash zsh fish'
+complete -c ptemplator -n 'not __fish_seen_subcommand_from completions' -F
+complete -c ptemplator -n 'string match -q -- "-*" (commandline -ct)' -a '(__ptemplator_vars)'
+@FLAG_LINES@
+"#;
+
+/// Completion script of `shell`, it completes the commands, the options and the variables of the
+/// template on the command line (as `--<VarName>`)
+pub fn script(shell: Shell) -> String {
+    let flags_with = |matches: fn(&FlagValue) -> bool, separator: &str| {
+        FLAGS
+            .iter()
+            .filter(|(_, value)| matches(value))
+            .map(|(flag, _)| *flag)
+            .collect::<Vec<&str>>()
+            .join(separator)
+    };
+    let (template, separator) = match shell {
+        Shell::Bash => (BASH, "|"),
+        Shell::Zsh => (ZSH, "|"),
+        Shell::Fish => (FISH, " "),
+    };
+    let choices: Vec<String> = FLAGS
+        .iter()
+        .filter_map(|(flag, value)| match value {
+            FlagValue::Choice(choices) => Some((flag, choices.join(" "))),
+            _ => None,
+        })
+        .map(|(flag, choices)| match shell {
+            Shell::Bash => format!(
+                "        {}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;",
+                flag, choices
+            ),
+            _ => format!("        {}) compadd -- {}; return ;;", flag, choices),
+        })
+        .collect();
+    let flag_lines: Vec<String> = FLAGS
+        .iter()
+        .map(|(flag, value)| {
+            let option = format!("complete -c ptemplator -l {}", &flag[2..]);
+            match value {
+                FlagValue::None => option,
+                FlagValue::Path => format!("{} -r -F", option),
+                FlagValue::Text => format!("{} -x", option),
+                FlagValue::Choice(choices) => format!("{} -x -a '{}'", option, choices.join(" ")),
+            }
+        })
+        .collect();
+    template
+        .replace(
+            "@PATH_FLAGS@",
+            &flags_with(|value| *value == FlagValue::Path, separator),
+        )
+        .replace(
+            "@TEXT_FLAGS@",
+            &flags_with(|value| *value == FlagValue::Text, separator),
+        )
+        .replace(
+            "@VALUE_FLAGS@",
+            &flags_with(|value| *value != FlagValue::None, separator),
+        )
+        .replace("@CHOICES@", &choices.join("\n"))
+        .replace("@FLAG_LINES@", &flag_lines.join("\n"))
+        .replace("@FLAGS@", &flags_with(|_| true, " "))
+        .replace("@COMMANDS@", &COMMANDS.join(" "))
+        .replace("@LIST_VARS@", LIST_VARS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::script;
+    use crate::cli::{Shell, FLAGS};
+
+    #[test]
+    fn test_scripts() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let script = script(shell);
+            assert!(!script.contains('@'), "{}", script);
+            assert!(script.contains("ptemplator vars \"$template\""));
+            for (flag, _) in FLAGS {
+                assert!(script.contains(&flag[2..]), "{:?} {}", shell, flag);
+            }
+        }
+        assert!(script(Shell::Bash).contains("--output|--save-vars) COMPREPLY"));
+        assert!(script(Shell::Fish)
+            .contains("complete -c ptemplator -l newline -x -a 'lf crlf native'"));
+    }
+}
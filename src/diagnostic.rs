@@ -0,0 +1,327 @@
+use std::fmt;
+use std::path;
+
+use crate::back::TextGenError;
+use crate::front::{ParseError, Position, Range};
+
+/// Error of any stage of the pipeline, along with where it happened
+#[derive(Debug, PartialEq)]
+pub enum RenderError {
+    Parse {
+        error: ParseError,
+        file: Option<path::PathBuf>,
+        range: Option<Range>,
+    },
+    TextGen {
+        error: TextGenError,
+        file: Option<path::PathBuf>,
+        range: Option<Range>,
+    },
+}
+
+/// Problems of a template are reported as render errors
+pub type Diagnostic = RenderError;
+
+impl From<ParseError> for RenderError {
+    fn from(error: ParseError) -> Self {
+        let range = error.range();
+        RenderError::Parse {
+            error,
+            file: None,
+            range,
+        }
+    }
+}
+
+impl From<TextGenError> for RenderError {
+    fn from(error: TextGenError) -> Self {
+        let range = Some(error.range());
+        RenderError::TextGen {
+            error,
+            file: None,
+            range,
+        }
+    }
+}
+
+impl RenderError {
+    /// Same error, happened in the template read from `file_path`
+    pub fn in_file(self, file_path: &path::Path) -> Self {
+        match self {
+            Self::Parse { error, range, .. } => Self::Parse {
+                error,
+                file: Some(file_path.to_path_buf()),
+                range,
+            },
+            Self::TextGen { error, range, .. } => Self::TextGen {
+                error,
+                file: Some(file_path.to_path_buf()),
+                range,
+            },
+        }
+    }
+
+    pub fn file(&self) -> Option<&path::Path> {
+        match self {
+            Self::Parse { file, .. } | Self::TextGen { file, .. } => file.as_deref(),
+        }
+    }
+
+    /// Part of the template the problem is about (if it's about a particular part)
+    pub fn range(&self) -> Option<Range> {
+        match self {
+            Self::Parse { range, .. } | Self::TextGen { range, .. } => *range,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Self::Parse { error, .. } => error.message().to_string(),
+            Self::TextGen { error, .. } => error.message(),
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Parse { error, .. } => error.code(),
+            Self::TextGen { error, .. } => error.code(),
+        }
+    }
+
+    /// `{file, line, column, end_line, end_column, code, message}` for tools reading the errors.
+    /// Positions start from 1 and the end is just past the range, the parts that are not known are
+    /// `null`
+    pub fn to_json(&self) -> serde_json::Value {
+        let (line, column, end_line, end_column) = match (self, self.range()) {
+            (_, Some(range)) => (
+                Some(range.start_pos.line + 1),
+                Some(range.start_pos.column + 1),
+                Some(range.end_pos.line + 1),
+                Some(range.end_pos.column + 1),
+            ),
+            (
+                Self::Parse {
+                    error: ParseError::FailedToReadLine(line),
+                    ..
+                },
+                None,
+            ) => (Some(line + 1), None, None, None),
+            _ => (None, None, None, None),
+        };
+        serde_json::json!({
+            "file": self.file().map(|file| file.display().to_string()),
+            "line": line,
+            "column": column,
+            "end_line": end_line,
+            "end_column": end_column,
+            "code": self.code(),
+            "message": self.message(),
+        })
+    }
+
+    // `<file>:<line>:<column>` with the parts that are known
+    fn location(&self) -> Option<String> {
+        let position = match (self, self.range()) {
+            (_, Some(range)) => Some(format!(
+                "{}:{}",
+                range.start_pos.line + 1,
+                range.start_pos.column + 1
+            )),
+            (
+                Self::Parse {
+                    error: ParseError::FailedToReadLine(line),
+                    ..
+                },
+                None,
+            ) => Some((line + 1).to_string()),
+            _ => None,
+        };
+        match (self.file(), position) {
+            (Some(file), Some(position)) => Some(format!("{}:{}", file.display(), position)),
+            (Some(file), None) => Some(file.display().to_string()),
+            (None, position) => position,
+        }
+    }
+
+    /// Human readable report showing the offending line of `source` (the template the error
+    /// happened in) with the range underlined. ANSI colors are used when `color` is set
+    pub fn report(&self, source: &str, color: bool) -> String {
+        let heading = format!(
+            "{}: {}",
+            paint("error", ERROR_STYLE, color),
+            paint(&self.message(), BOLD_STYLE, color)
+        );
+        let location = match self.location() {
+            Some(location) => location,
+            None => return heading,
+        };
+        let (range, line) = match self.range() {
+            Some(range) => match source.lines().nth(range.start_pos.line) {
+                Some(line) => (range, line),
+                None => return format!("{}\n  --> {}", heading, location),
+            },
+            None => return format!("{}\n  --> {}", heading, location),
+        };
+        let start_pos = range.start_pos;
+        let line_number = (start_pos.line + 1).to_string();
+        let gutter = " ".repeat(line_number.len());
+        format!(
+            "{}\n{}{} {}\n{} {}\n{} {} {}\n{} {} {}{}",
+            heading,
+            gutter,
+            paint("-->", GUTTER_STYLE, color),
+            location,
+            gutter,
+            paint("|", GUTTER_STYLE, color),
+            paint(&line_number, GUTTER_STYLE, color),
+            paint("|", GUTTER_STYLE, color),
+            line,
+            gutter,
+            paint("|", GUTTER_STYLE, color),
+            " ".repeat(start_pos.column),
+            paint(&underline(line, &range, &start_pos), ERROR_STYLE, color)
+        )
+    }
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.location() {
+            Some(location) => write!(f, "{}: {}", location, self.message()),
+            None => write!(f, "{}", self.message()),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Parse { error, .. } => Some(error),
+            Self::TextGen { error, .. } => Some(error),
+        }
+    }
+}
+
+const ERROR_STYLE: &str = "1;31";
+const BOLD_STYLE: &str = "1";
+const GUTTER_STYLE: &str = "1;34";
+
+fn paint(text: &str, style: &str, color: bool) -> String {
+    if color {
+        format!("\x1b[{}m{}\x1b[0m", style, text)
+    } else {
+        text.to_string()
+    }
+}
+
+// Carets under the part of `line` covered by `range` (ranges spanning lines are cut at the end
+// of the first one)
+fn underline(line: &str, range: &Range, start_pos: &Position) -> String {
+    let line_length = line.chars().count();
+    let end_column = if range.end_pos.line == start_pos.line {
+        range.end_pos.column
+    } else {
+        line_length
+    };
+    "^".repeat(
+        end_column
+            .min(line_length)
+            .saturating_sub(start_pos.column)
+            .max(1),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path;
+
+    use super::RenderError;
+    use crate::front::{ParseError, Position, Range};
+
+    #[test]
+    fn test_report() {
+        let source = "first line\nHello ${name}!";
+        let range = Range {
+            start_pos: Position { line: 1, column: 6 },
+            end_pos: Position {
+                line: 1,
+                column: 13,
+            },
+        };
+        let error = RenderError::from(ParseError::VariableNotFound(range))
+            .in_file(path::Path::new("t.txt"));
+        assert_eq!(
+            error.report(source, false),
+            "error: variable not found\n \
+             --> t.txt:2:7\n  \
+             |\n\
+             2 | Hello ${name}!\n  \
+             |       ^^^^^^^"
+        );
+        let colored = error.report(source, true);
+        assert!(colored.starts_with("\x1b[1;31merror\x1b[0m: \x1b[1mvariable not found\x1b[0m"));
+        let error = RenderError::from(ParseError::InvalidFilePath);
+        assert_eq!(error.report(source, false), "error: invalid file path");
+        let error = error.in_file(path::Path::new("t.txt"));
+        assert_eq!(
+            error.report(source, false),
+            "error: invalid file path\n  --> t.txt"
+        );
+    }
+
+    #[test]
+    fn test_to_json() {
+        let range = Range {
+            start_pos: Position { line: 1, column: 6 },
+            end_pos: Position {
+                line: 1,
+                column: 13,
+            },
+        };
+        let error = RenderError::from(ParseError::VariableNotFound(range))
+            .in_file(path::Path::new("t.txt"));
+        assert_eq!(
+            error.to_json(),
+            serde_json::json!({
+                "file": "t.txt",
+                "line": 2,
+                "column": 7,
+                "end_line": 2,
+                "end_column": 14,
+                "code": "variable_not_found",
+                "message": "variable not found",
+            })
+        );
+        let error = RenderError::from(ParseError::FailedToReadLine(4));
+        assert_eq!(
+            error.to_json(),
+            serde_json::json!({
+                "file": null,
+                "line": 5,
+                "column": null,
+                "end_line": null,
+                "end_column": null,
+                "code": "failed_to_read_line",
+                "message": "failed to read line",
+            })
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let range = Range {
+            start_pos: Position { line: 0, column: 2 },
+            end_pos: Position { line: 0, column: 6 },
+        };
+        let error = RenderError::from(ParseError::InvalidFilter(range));
+        assert_eq!(error.to_string(), "1:3: invalid filter (use `var|filter`)");
+        assert_eq!(
+            error.in_file(path::Path::new("t.txt")).to_string(),
+            "t.txt:1:3: invalid filter (use `var|filter`)"
+        );
+        assert_eq!(
+            ParseError::FailedToReadLine(4).to_string(),
+            "5: failed to read line"
+        );
+    }
+}
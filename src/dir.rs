@@ -0,0 +1,83 @@
+use std::fs;
+use std::io;
+use std::path;
+
+use ptemplator::glob_matches;
+
+/// Paths (relative to `dir`) of every file under `dir`, sorted so that the output doesn't depend on
+/// the file system. `skip` is not walked (so the output directory can be inside `dir`)
+pub fn files(dir: &path::Path, skip: &path::Path) -> io::Result<Vec<path::PathBuf>> {
+    let skip = skip.canonicalize().ok();
+    let mut files = vec![];
+    let mut pending = vec![path::PathBuf::new()];
+    while let Some(relative_dir) = pending.pop() {
+        for entry in fs::read_dir(dir.join(&relative_dir))? {
+            let entry = entry?;
+            let relative_path = relative_dir.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                if skip.is_none() || entry.path().canonicalize().ok() != skip {
+                    pending.push(relative_path);
+                }
+            } else {
+                files.push(relative_path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Whether the file at `relative_path` should be rendered. Patterns without a `/` are matched
+/// against the file name, the rest against the whole relative path
+pub fn is_template(relative_path: &path::Path, pattern: Option<&str>) -> bool {
+    let pattern = match pattern {
+        Some(pattern) => pattern,
+        None => return true,
+    };
+    let path = if pattern.contains('/') {
+        relative_path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    } else {
+        relative_path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+    glob_matches(pattern, &path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path;
+
+    use super::{files, is_template};
+
+    #[test]
+    fn test_files() {
+        let dir = std::env::temp_dir().join("ptemplator_test_files");
+        std::fs::create_dir_all(dir.join("sub/out")).unwrap();
+        std::fs::write(dir.join("b.txt"), "").unwrap();
+        std::fs::write(dir.join("sub/a.tmpl"), "").unwrap();
+        std::fs::write(dir.join("sub/out/c.txt"), "").unwrap();
+        assert_eq!(
+            files(&dir, &dir.join("sub/out")).unwrap(),
+            vec![
+                path::PathBuf::from("b.txt"),
+                path::PathBuf::from("sub/a.tmpl")
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_is_template() {
+        assert!(is_template(path::Path::new("sub/a.tmpl"), None));
+        assert!(is_template(path::Path::new("sub/a.tmpl"), Some("*.tmpl")));
+        assert!(!is_template(path::Path::new("sub/a.txt"), Some("*.tmpl")));
+        assert!(is_template(path::Path::new("sub/a.txt"), Some("sub/*")));
+        assert!(!is_template(path::Path::new("a.txt"), Some("sub/*")));
+    }
+}
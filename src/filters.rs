@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+
+/// Transformation applied to a value before it is inserted (`${var|name}`). Filters are shared by
+/// templates rendered in parallel
+pub trait Filter: Send + Sync {
+    fn apply(&self, value: &str) -> String;
+}
+
+impl<F: Fn(&str) -> String + Send + Sync> Filter for F {
+    fn apply(&self, value: &str) -> String {
+        self(value)
+    }
+}
+
+/// Filters that can be used in templates, by name
+pub struct Filters {
+    filters: HashMap<String, Box<dyn Filter>>,
+}
+
+impl Filters {
+    /// Registry without any filters (use `default` to get the built in ones)
+    pub fn empty() -> Filters {
+        Filters {
+            filters: HashMap::new(),
+        }
+    }
+
+    pub fn register<F: Filter + 'static>(&mut self, name: &str, filter: F) {
+        self.filters.insert(name.to_string(), Box::new(filter));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Filter> {
+        self.filters.get(name).map(|filter| filter.as_ref())
+    }
+}
+
+impl Default for Filters {
+    fn default() -> Self {
+        let mut filters = Filters::empty();
+        filters.register("upper", |value: &str| value.to_uppercase());
+        filters.register("lower", |value: &str| value.to_lowercase());
+        filters.register("trim", |value: &str| value.trim().to_string());
+        filters.register("pascal", |value: &str| {
+            words(value)
+                .iter()
+                .map(|word| capitalize(word))
+                .collect::<String>()
+        });
+        filters.register("snake", |value: &str| words(value).join("_"));
+        filters.register("kebab", |value: &str| words(value).join("-"));
+        filters
+    }
+}
+
+// Lower case words of an identifier like value (`my_widget`, `MyWidget`, `my-widget`, `HTTPServer`).
+// Words are separated by characters other than letters and digits and by changes of case
+fn words(value: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let chars: Vec<char> = value.chars().collect();
+    for (i, c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            continue;
+        }
+        let previous = i.checked_sub(1).map(|i| chars[i]);
+        let next = chars.get(i + 1);
+        // `aB` and the `S` of `HTTPServer` start words
+        let starts_word = c.is_uppercase()
+            && previous.is_some_and(|previous| {
+                previous.is_lowercase()
+                    || previous.is_ascii_digit()
+                    || (previous.is_uppercase() && next.is_some_and(|next| next.is_lowercase()))
+            });
+        if starts_word && !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        word.extend(c.to_lowercase());
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Filters;
+
+    #[test]
+    fn test_builtin_filters() {
+        let filters = Filters::default();
+        assert_eq!(filters.get("upper").unwrap().apply("aB c"), "AB C");
+        assert_eq!(filters.get("lower").unwrap().apply("aB c"), "ab c");
+        assert_eq!(filters.get("trim").unwrap().apply("  aB c "), "aB c");
+        assert!(filters.get("reverse").is_none());
+    }
+
+    #[test]
+    fn test_case_filters() {
+        let filters = Filters::default();
+        let apply = |name: &str, value: &str| filters.get(name).unwrap().apply(value);
+        for value in [
+            "my_widget",
+            "MyWidget",
+            "my-widget",
+            "myWidget",
+            " My widget ",
+        ] {
+            assert_eq!(apply("pascal", value), "MyWidget");
+            assert_eq!(apply("snake", value), "my_widget");
+            assert_eq!(apply("kebab", value), "my-widget");
+        }
+        assert_eq!(apply("snake", "HTTPServer2Go"), "http_server2_go");
+        assert_eq!(apply("pascal", "parse_json_v2"), "ParseJsonV2");
+    }
+
+    #[test]
+    fn test_custom_filter() {
+        let mut filters = Filters::default();
+        filters.register("reverse", |value: &str| value.chars().rev().collect());
+        assert_eq!(filters.get("reverse").unwrap().apply("abc"), "cba");
+    }
+}
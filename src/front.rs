@@ -1,5 +1,16 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::fmt;
+use std::io::BufRead;
+use std::sync::Arc;
 
+use serde::Serialize;
+
+use crate::builtins::{is_builtin, is_valid_format, takes_format};
+use crate::resolver::{ChainResolver, EnvResolver, Resolver};
+use crate::value::Value;
+
+#[derive(Debug, PartialEq)]
 pub enum ParseError {
     UnexpectedToken(Position),
     InvalidFilePath,
@@ -7,37 +18,112 @@ pub enum ParseError {
     VariableNotFound(Range),
     FileNotFound(Range),
     FailedToReadLine(usize),
+    InvalidCondition(Range),
+    InvalidLoop(Range),
+    InvalidFilter(Range),
+    UnclosedBlock(Range),
+    UnexpectedBlockEnd(Range),
+    InvalidLineRange(Range),
+    InvalidFormat(Range),
+    InvalidDefinition(Range),
+    UndefinedMacro(Range),
+    WrongArgumentCount(Range),
+    NestingTooDeep(Range),
 }
 
-impl fmt::Debug for ParseError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl ParseError {
+    /// Part of the template the error is about (if it's about a particular part)
+    pub fn range(&self) -> Option<Range> {
         match self {
-            Self::UnexpectedToken(pos) => {
-                write!(f, "{:?} : Unexpected token", pos)
-            }
-            Self::InvalidFilePath => {
-                write!(f, ": Invalid file path")
-            }
-            Self::FailedToOpenFile => {
-                write!(f, ": Failed to open file")
-            }
-            Self::FailedToReadLine(l) => {
-                write!(f, "{}: Failed to read line", l)
-            }
-            Self::VariableNotFound(pos) => {
-                write!(f, "{:?} : variable not found", pos)
-            }
-            Self::FileNotFound(pos) => {
-                write!(f, "{:?} : file not found", pos)
+            Self::UnexpectedToken(pos) => Some(span(pos, pos)),
+            Self::VariableNotFound(range)
+            | Self::FileNotFound(range)
+            | Self::InvalidCondition(range)
+            | Self::InvalidLoop(range)
+            | Self::InvalidFilter(range)
+            | Self::UnclosedBlock(range)
+            | Self::UnexpectedBlockEnd(range)
+            | Self::InvalidLineRange(range)
+            | Self::InvalidFormat(range)
+            | Self::InvalidDefinition(range)
+            | Self::UndefinedMacro(range)
+            | Self::WrongArgumentCount(range)
+            | Self::NestingTooDeep(range) => Some(*range),
+            Self::InvalidFilePath | Self::FailedToOpenFile | Self::FailedToReadLine(_) => None,
+        }
+    }
+
+    pub(crate) fn message(&self) -> &'static str {
+        match self {
+            Self::UnexpectedToken(_) => "unexpected token",
+            Self::InvalidFilePath => "invalid file path",
+            Self::FailedToOpenFile => "failed to open file",
+            Self::FailedToReadLine(_) => "failed to read line",
+            Self::VariableNotFound(_) => "variable not found",
+            Self::FileNotFound(_) => "file not found",
+            Self::InvalidCondition(_) => "invalid condition",
+            Self::InvalidLoop(_) => "invalid loop (use `for <item> in <items>`)",
+            Self::InvalidFilter(_) => "invalid filter (use `var|filter`)",
+            Self::UnclosedBlock(_) => "block is not closed",
+            Self::UnexpectedBlockEnd(_) => "unexpected end of block",
+            Self::InvalidLineRange(_) => "invalid line range (use `...file:start-end`)",
+            Self::InvalidFormat(_) => {
+                "invalid time format (use %Y, %y, %m, %d, %H, %M, %S, %s or %%)"
             }
+            Self::InvalidDefinition(_) => "invalid definition (use `def <name>(<parameter>, ...)`)",
+            Self::UndefinedMacro(_) => "macro is not defined",
+            Self::WrongArgumentCount(_) => "wrong number of arguments for the macro",
+            Self::NestingTooDeep(_) => "blocks are nested too deep (256 at most)",
+        }
+    }
+
+    /// Name of the kind of error, which stays the same when the message changes
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::UnexpectedToken(_) => "unexpected_token",
+            Self::InvalidFilePath => "invalid_file_path",
+            Self::FailedToOpenFile => "failed_to_open_file",
+            Self::FailedToReadLine(_) => "failed_to_read_line",
+            Self::VariableNotFound(_) => "variable_not_found",
+            Self::FileNotFound(_) => "file_not_found",
+            Self::InvalidCondition(_) => "invalid_condition",
+            Self::InvalidLoop(_) => "invalid_loop",
+            Self::InvalidFilter(_) => "invalid_filter",
+            Self::UnclosedBlock(_) => "unclosed_block",
+            Self::UnexpectedBlockEnd(_) => "unexpected_block_end",
+            Self::InvalidLineRange(_) => "invalid_line_range",
+            Self::InvalidFormat(_) => "invalid_format",
+            Self::InvalidDefinition(_) => "invalid_definition",
+            Self::UndefinedMacro(_) => "undefined_macro",
+            Self::WrongArgumentCount(_) => "wrong_argument_count",
+            Self::NestingTooDeep(_) => "nesting_too_deep",
+        }
+    }
+}
+
+// Positions are written starting from 1, the way editors show them
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self, self.range()) {
+            (_, Some(range)) => write!(
+                f,
+                "{}:{}: {}",
+                range.start_pos.line + 1,
+                range.start_pos.column + 1,
+                self.message()
+            ),
+            (Self::FailedToReadLine(line), None) => write!(f, "{}: {}", line + 1, self.message()),
+            (_, None) => write!(f, "{}", self.message()),
         }
     }
 }
 
-#[derive(PartialEq, Eq, Clone, Copy)]
+impl std::error::Error for ParseError {}
+
+#[derive(PartialEq, Eq, Clone, Copy, Serialize)]
 pub struct Range {
-    start_pos: Position,
-    end_pos: Position,
+    pub start_pos: Position,
+    pub end_pos: Position,
 }
 
 impl fmt::Debug for Range {
@@ -55,11 +141,11 @@ impl From<(&Position, &Position)> for Range {
     }
 }
 
-// NOTE: positions are starting from 0
-#[derive(PartialEq, Eq, Clone, Copy)]
+// NOTE: positions are starting from 0. Columns count chars (unicode scalar values) not bytes
+#[derive(PartialEq, Eq, Clone, Copy, Serialize)]
 pub struct Position {
-    line: usize,
-    column: usize,
+    pub line: usize,
+    pub column: usize,
 }
 
 impl fmt::Debug for Position {
@@ -68,231 +154,1373 @@ impl fmt::Debug for Position {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
 pub enum Token {
     Word { text: String, range: Range },
     Punctuation { value: char, pos: Position },
+    // Whitespace other than spaces (tabs etc.), the spaces at the end of a line and the line break
+    // (`\n` or `\r\n`) ending it. Spaces between tokens are implied by their positions, so the
+    // source is reconstructed exactly
+    Whitespace { text: String, range: Range },
 }
 
 impl Token {
-    fn start_pos(&self) -> Position {
+    pub fn start_pos(&self) -> Position {
         match self {
-            Token::Word { range, .. } => range.start_pos,
+            Token::Word { range, .. } | Token::Whitespace { range, .. } => range.start_pos,
             Token::Punctuation { pos, .. } => *pos,
         }
     }
+
+    pub fn range(&self) -> Range {
+        match self {
+            Token::Word { range, .. } | Token::Whitespace { range, .. } => *range,
+            Token::Punctuation { pos, .. } => span(pos, pos),
+        }
+    }
+
+    pub fn is_line_break(&self) -> bool {
+        matches!(self, Token::Whitespace { text, .. } if text.ends_with('\n'))
+    }
 }
 
-impl std::string::ToString for Token {
-    fn to_string(&self) -> String {
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Token::Word { text, .. } => text.to_string(),
-            Token::Punctuation { value, .. } => value.to_string(),
+            Token::Word { text, .. } | Token::Whitespace { text, .. } => write!(f, "{}", text),
+            Token::Punctuation { value, .. } => write!(f, "{}", value),
         }
     }
 }
 
 pub fn reconstruct_text(tokens: &[Token]) -> String {
-    let mut current_line = 0;
-    let mut current_column = 0;
-    let mut parts: Vec<String> = vec![];
+    let mut text = String::new();
+    append_text(
+        tokens,
+        &mut Position { line: 0, column: 0 },
+        "\n",
+        &mut text,
+    );
+    text
+}
+
+// Append the text of `tokens` to `text`, which ends at `current`. Lines that don't end with a line
+// break token (the lines of generated text) are ended with `line_break`
+pub(crate) fn append_text(
+    tokens: &[Token],
+    current: &mut Position,
+    line_break: &str,
+    text: &mut String,
+) {
     for token in tokens {
         let Position { line, column } = token.start_pos();
-        while current_line < line {
-            parts.push("\n".to_string());
-            current_line += 1;
-            current_column = 0;
+        while current.line < line {
+            text.push_str(line_break);
+            current.line += 1;
+            current.column = 0;
         }
-        while current_column < column {
-            parts.push(" ".to_string());
-            current_column += 1;
+        while current.column < column {
+            text.push(' ');
+            current.column += 1;
         }
         let part = token.to_string();
-        current_column += part.len();
-        parts.push(part);
+        text.push_str(&part);
+        if part.ends_with('\n') {
+            current.line += 1;
+            current.column = 0;
+        } else {
+            current.column += part.chars().count();
+        }
     }
-    parts.concat()
 }
 
-pub fn create_tokens(text: String, line: usize) -> Result<Vec<Token>, ParseError> {
-    let mut char_buffer = vec![];
-    let mut tokens = vec![];
-    let mut start = 0;
-    // TODO: turn in to iterator
-    for (i, char) in text.chars().enumerate() {
-        match char {
-            ' ' => {
-                tokens.push(create_token(&char_buffer, line, start, i));
-                char_buffer.clear();
-                start = i + 1;
+/// Tokens of a template, each line is only read and split when its tokens are needed
+pub struct Tokens<'a> {
+    // lines along with their line breaks
+    lines: Box<dyn Iterator<Item = Result<String, ParseError>> + 'a>,
+    // tokens of the line read last that are not consumed yet
+    line_tokens: std::vec::IntoIter<Token>,
+    line: usize,
+}
+
+impl<'a> Tokens<'a> {
+    pub fn from_text(text: &'a str) -> Self {
+        Self::new(text.split_inclusive('\n').map(|line| Ok(line.to_string())))
+    }
+
+    /// Lines are read as the tokens are consumed, the tokens end at the first line that can't be
+    /// read
+    pub fn from_reader<R: BufRead + 'a>(mut reader: R) -> Self {
+        let mut i = 0;
+        Self::new(std::iter::from_fn(move || {
+            let mut line = vec![];
+            let line = match reader.read_until(b'\n', &mut line) {
+                Ok(0) => return None,
+                Ok(_) => String::from_utf8(line).map_err(|_| ParseError::FailedToReadLine(i)),
+                Err(_) => Err(ParseError::FailedToReadLine(i)),
+            };
+            i += 1;
+            Some(line)
+        }))
+    }
+
+    fn new<I: Iterator<Item = Result<String, ParseError>> + 'a>(lines: I) -> Self {
+        Tokens {
+            lines: Box::new(lines),
+            line_tokens: vec![].into_iter(),
+            line: 0,
+        }
+    }
+}
+
+impl Iterator for Tokens<'_> {
+    type Item = Result<Token, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(token) = self.line_tokens.next() {
+                return Some(Ok(token));
             }
-            c if c.is_whitespace() => {
-                return Err(ParseError::UnexpectedToken(Position { line, column: i }));
+            match self.lines.next()? {
+                Ok(line) => {
+                    self.line_tokens = tokenize_line(&line, self.line).into_iter();
+                    self.line += 1;
+                }
+                Err(err) => {
+                    self.lines = Box::new(std::iter::empty());
+                    return Some(Err(err));
+                }
             }
-            c if c.is_ascii_punctuation() => {
-                // Add chars upto this
-                // TODO: common code refactor
-                tokens.push(create_token(&char_buffer, line, start, i));
-                char_buffer.clear();
-
-                char_buffer.push(char);
-                tokens.push(create_token(&char_buffer, line, i, i + 1));
-                char_buffer.clear();
-                start = i + 1;
+        }
+    }
+}
+
+// Line break `line` (read along with it) ends with, if any
+pub(crate) fn split_line_break(line: &str) -> (&str, &str) {
+    let text = line
+        .strip_suffix("\r\n")
+        .or_else(|| line.strip_suffix('\n'))
+        .unwrap_or(line);
+    (text, &line[text.len()..])
+}
+
+// Tokens of `line` (the line with the number `line_number`), the line break it ends with is a
+// token of its own
+pub(crate) fn tokenize_line(line: &str, line_number: usize) -> Vec<Token> {
+    let (text, line_break) = split_line_break(line);
+    let chars: Vec<char> = text.chars().collect();
+    let mut column = 0;
+    let mut tokens: Vec<Token> =
+        std::iter::from_fn(|| next_token(&chars, line_number, &mut column)).collect();
+    if !line_break.is_empty() {
+        tokens.push(Token::Whitespace {
+            text: line_break.to_string(),
+            range: Range {
+                start_pos: Position {
+                    line: line_number,
+                    column,
+                },
+                end_pos: Position {
+                    line: line_number,
+                    column: column + line_break.len(),
+                },
+            },
+        });
+    }
+    tokens
+}
+
+// Token of `chars` (the text of `line`) starting from `column`, which is moved past it
+fn next_token(chars: &[char], line: usize, column: &mut usize) -> Option<Token> {
+    // spaces are implied by the positions of the tokens, unless they end the line
+    let spaces_start = *column;
+    while chars.get(*column) == Some(&' ') {
+        *column += 1;
+    }
+    if *column == chars.len() && spaces_start < *column {
+        return Some(Token::Whitespace {
+            text: " ".repeat(*column - spaces_start),
+            range: Range {
+                start_pos: Position {
+                    line,
+                    column: spaces_start,
+                },
+                end_pos: Position {
+                    line,
+                    column: *column,
+                },
+            },
+        });
+    }
+    let start = *column;
+    let first = *chars.get(start)?;
+    let is_part = |c: &char| -> bool {
+        if first.is_whitespace() {
+            // consecutive whitespace is kept in a single token
+            c.is_whitespace() && *c != ' '
+        } else {
+            !c.is_whitespace() && !c.is_ascii_punctuation()
+        }
+    };
+    let length = if first.is_ascii_punctuation() {
+        1
+    } else {
+        chars[start..].iter().take_while(|c| is_part(c)).count()
+    };
+    *column += length;
+    let start_pos = Position {
+        line,
+        column: start,
+    };
+    let range = Range {
+        start_pos,
+        end_pos: Position {
+            line,
+            column: *column,
+        },
+    };
+    let text = chars[start..*column].iter().collect();
+    if first.is_ascii_punctuation() {
+        Some(Token::Punctuation {
+            value: first,
+            pos: start_pos,
+        })
+    } else if first.is_whitespace() {
+        Some(Token::Whitespace { text, range })
+    } else {
+        Some(Token::Word { text, range })
+    }
+}
+
+pub fn create_tokens(text: String, line: usize) -> Result<Vec<Token>, ParseError> {
+    Ok(tokenize_line(&text, line))
+}
+
+pub fn tokenize_text(text: &str) -> Result<Vec<Token>, ParseError> {
+    Tokens::from_text(text).collect()
+}
+
+pub fn tokenize_reader<R: BufRead>(reader: R) -> Result<Vec<Token>, ParseError> {
+    Tokens::from_reader(reader).collect()
+}
+
+/// Tokens of every line that could be tokenized, along with the errors of the ones that could not
+pub fn tokenize_reader_with_errors<R: BufRead>(reader: R) -> (Vec<Token>, Vec<ParseError>) {
+    let mut tokens = vec![];
+    let mut errors = vec![];
+    for token in Tokens::from_reader(reader) {
+        match token {
+            Ok(token) => tokens.push(token),
+            Err(err) => errors.push(err),
+        }
+    }
+    (tokens, errors)
+}
+
+pub type Identifier = String;
+
+/// Text around tags, `${` and `}` unless changed (`{{` and `}}` for templates of shell scripts).
+/// Delimiters are made of ASCII punctuation, each character of which is a token of its own
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delimiters {
+    open: Vec<char>,
+    close: Vec<char>,
+}
+
+impl Delimiters {
+    /// `None` if either delimiter is empty or has characters other than ASCII punctuation (`\\`
+    /// escapes the open delimiter so it can't be part of one)
+    pub fn new(open: &str, close: &str) -> Option<Delimiters> {
+        let is_valid = |delimiter: &str| {
+            !delimiter.is_empty()
+                && delimiter
+                    .chars()
+                    .all(|c| c.is_ascii_punctuation() && c != '\\')
+        };
+        (is_valid(open) && is_valid(close)).then(|| Delimiters {
+            open: open.chars().collect(),
+            close: close.chars().collect(),
+        })
+    }
+
+    // Text of a tag with `content` between the delimiters
+    pub(crate) fn tag(&self, content: &str) -> String {
+        let close: String = self.close.iter().collect();
+        format!("{}{}{}", self.open_text(), content, close)
+    }
+
+    pub(crate) fn open_text(&self) -> String {
+        self.open.iter().collect()
+    }
+
+    // Whether `tokens` starts with the open delimiter
+    fn opens(&self, tokens: &[Token]) -> bool {
+        starts_with_punctuation(tokens, &self.open)
+    }
+
+    // Whether `tokens` starts with the close delimiter
+    fn closes(&self, tokens: &[Token]) -> bool {
+        starts_with_punctuation(tokens, &self.close)
+    }
+}
+
+impl Default for Delimiters {
+    fn default() -> Self {
+        Delimiters::new("${", "}").unwrap()
+    }
+}
+
+fn starts_with_punctuation(tokens: &[Token], chars: &[char]) -> bool {
+    tokens.len() >= chars.len()
+        && tokens
+            .iter()
+            .zip(chars)
+            .all(|(token, c)| matches!(token, Token::Punctuation { value, .. } if value == c))
+}
+
+// NOTE: symbol ranges are end exclusive (same as word tokens)
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub enum Symbol {
+    Word {
+        text: String,
+        range: Range,
+    },
+
+    Replace {
+        identifier: Identifier,
+        default: Option<String>,
+        // format of the builtin time variables (`${__date:%Y}`)
+        format: Option<String>,
+        filters: Vec<Identifier>,
+        range: Range,
+    },
+
+    Spread {
+        identifier: Identifier,
+        lines: Option<LineRange>,
+        range: Range,
+    },
+
+    Conditional {
+        condition: Condition,
+        body: Vec<Symbol>,
+        open_range: Range,
+        close_range: Range,
+    },
+
+    Loop {
+        variable: Identifier,
+        iterable: Identifier,
+        body: Vec<Symbol>,
+        open_range: Range,
+        close_range: Range,
+    },
+
+    // `${# text}`, left out of the output
+    Comment {
+        text: String,
+        range: Range,
+    },
+
+    // `${def name(parameters)}body${enddef}`, written out where it's called instead
+    Definition {
+        name: Identifier,
+        parameters: Vec<Identifier>,
+        body: Vec<Symbol>,
+        open_range: Range,
+        close_range: Range,
+    },
+
+    // `${name(arguments)}`, the arguments are text
+    Call {
+        name: Identifier,
+        arguments: Vec<String>,
+        range: Range,
+    },
+}
+
+/// Lines of a spread file to insert, starting from 1 and inclusive. Without an `end` the lines go
+/// on to the end of the file
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: Option<usize>,
+}
+
+impl fmt::Display for LineRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.end {
+            Some(end) => write!(f, "{}-{}", self.start, end),
+            None => write!(f, "{}-", self.start),
+        }
+    }
+}
+
+impl Symbol {
+    pub fn range(&self) -> Range {
+        match self {
+            Symbol::Word { range, .. }
+            | Symbol::Replace { range, .. }
+            | Symbol::Spread { range, .. }
+            | Symbol::Comment { range, .. }
+            | Symbol::Call { range, .. } => *range,
+            Symbol::Conditional {
+                open_range,
+                close_range,
+                ..
             }
-            _ => {
-                char_buffer.push(char);
+            | Symbol::Loop {
+                open_range,
+                close_range,
+                ..
             }
+            | Symbol::Definition {
+                open_range,
+                close_range,
+                ..
+            } => Range {
+                start_pos: open_range.start_pos,
+                end_pos: close_range.end_pos,
+            },
         }
     }
-    Ok(tokens.into_iter().flatten().collect())
 }
 
-fn create_token(chars: &[char], line: usize, start: usize, end: usize) -> Option<Token> {
-    match chars.len() {
-        0 => None,
-        1 => {
-            assert_eq!(start + 1, end);
-            let char = chars[0];
-            let pos = Position {
-                line,
-                column: start,
-            };
-            if char.is_ascii_punctuation() {
-                Some(Token::Punctuation { value: char, pos })
-            } else {
-                Some(Token::Word {
-                    text: char.to_string(),
-                    range: Range {
-                        start_pos: pos,
-                        end_pos: Position { line, column: end },
-                    },
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum VariableKind {
+    Replace,
+    Spread,
+    Loop,
+}
+
+impl fmt::Display for VariableKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VariableKind::Replace => write!(f, "replace"),
+            VariableKind::Spread => write!(f, "spread"),
+            VariableKind::Loop => write!(f, "loop"),
+        }
+    }
+}
+
+/// Variable used by a `Replace` or `Spread` symbol or iterated by a `Loop`
+#[derive(Debug, PartialEq)]
+pub struct VariableUse {
+    pub identifier: Identifier,
+    pub kind: VariableKind,
+    pub range: Range,
+}
+
+/// Variables used by the symbols (including the ones inside blocks) in order. Loop variables and
+/// macro parameters are left out since they are bound by the template itself
+pub fn variable_uses(symbols: &[Symbol]) -> Vec<VariableUse> {
+    symbols
+        .iter()
+        .flat_map(|symbol| match symbol {
+            Symbol::Word { .. } | Symbol::Comment { .. } | Symbol::Call { .. } => vec![],
+            Symbol::Replace {
+                identifier, range, ..
+            } => vec![VariableUse {
+                identifier: identifier.to_string(),
+                kind: VariableKind::Replace,
+                range: *range,
+            }],
+            Symbol::Spread {
+                identifier, range, ..
+            } => vec![VariableUse {
+                identifier: identifier.to_string(),
+                kind: VariableKind::Spread,
+                range: *range,
+            }],
+            Symbol::Conditional { body, .. } => variable_uses(body),
+            Symbol::Loop {
+                variable,
+                iterable,
+                body,
+                open_range,
+                ..
+            } => vec![VariableUse {
+                identifier: iterable.to_string(),
+                kind: VariableKind::Loop,
+                range: *open_range,
+            }]
+            .into_iter()
+            .chain(
+                variable_uses(body)
+                    .into_iter()
+                    .filter(|each| each.identifier.split('.').next() != Some(variable.as_str())),
+            )
+            .collect(),
+            Symbol::Definition {
+                parameters, body, ..
+            } => variable_uses(body)
+                .into_iter()
+                .filter(|each| {
+                    let root = each.identifier.split('.').next().unwrap_or_default();
+                    !parameters.iter().any(|parameter| parameter == root)
                 })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Fragment defined by a `${def}` block
+#[derive(Clone)]
+pub(crate) struct Macro {
+    pub(crate) parameters: Vec<Identifier>,
+    pub(crate) body: Vec<Symbol>,
+    // Where the text of the body starts. A body that starts on the line after the `${def}` tag
+    // (and ends on the line before `${enddef}`) leaves out the lines of the tags
+    pub(crate) start: Position,
+}
+
+impl Macro {
+    fn new(
+        parameters: &[Identifier],
+        body: &[Symbol],
+        open_range: &Range,
+        close_range: &Range,
+    ) -> Self {
+        let is_blank = |symbol: &Symbol| matches!(symbol, Symbol::Word { text, .. } if text.chars().all(char::is_whitespace));
+        let open_line = open_range.end_pos.line;
+        let close_line = close_range.start_pos.line;
+        let mut text = body.iter().filter(|symbol| !is_blank(symbol));
+        let after_open = text
+            .clone()
+            .all(|symbol| symbol.range().start_pos.line > open_line);
+        let before_close = text.all(|symbol| symbol.range().end_pos.line < close_line);
+        // the line break of the line before `${enddef}` goes with the line of the tag
+        let is_last_break = |symbol: &Symbol| match symbol {
+            Symbol::Word { text, range } => {
+                text.ends_with('\n') && range.start_pos.line + 1 == close_line
             }
-        }
-        n => {
-            assert_eq!(start + n, end);
-            let start_pos = Position {
-                line,
-                column: start,
-            };
-            let end_pos = Position { line, column: end };
-            let text = chars.iter().collect();
-            Some(Token::Word {
-                text,
-                range: Range { start_pos, end_pos },
+            _ => false,
+        };
+        let body = body
+            .iter()
+            .filter(|symbol| {
+                let line = symbol.range().start_pos.line;
+                !(is_blank(symbol)
+                    && ((after_open && line == open_line)
+                        || (before_close && (line == close_line || is_last_break(symbol)))))
             })
+            .cloned()
+            .collect();
+        let start = if after_open {
+            Position {
+                line: open_line + 1,
+                column: 0,
+            }
+        } else {
+            open_range.end_pos
+        };
+        Macro {
+            parameters: parameters.to_vec(),
+            body,
+            start,
         }
     }
 }
 
-pub type Identifier = String;
-
-#[derive(Debug, PartialEq)]
-pub enum Symbol {
-    Word { text: String },
+/// Macros defined by the symbols (along with the ones defined in their bodies) by name, the ones
+/// defined later replace the earlier ones with the same name
+pub(crate) fn collect_definitions(
+    symbols: &[Symbol],
+    definitions: &mut HashMap<Identifier, Macro>,
+) {
+    for symbol in symbols {
+        if let Symbol::Definition {
+            name,
+            parameters,
+            body,
+            open_range,
+            close_range,
+        } = symbol
+        {
+            let definition = Macro::new(parameters, body, open_range, close_range);
+            definitions.insert(name.to_string(), definition);
+            collect_definitions(body, definitions);
+        }
+    }
+}
 
-    Replace { identifier: Identifier },
+// Calls of macros that are not defined before them or with the wrong number of arguments, the
+// errors are kept in the order of the template. `definitions` are the macros defined so far
+fn check_calls(
+    symbols: &[Symbol],
+    definitions: &mut HashMap<Identifier, Macro>,
+    errors: &mut Vec<ParseError>,
+) {
+    for symbol in symbols {
+        match symbol {
+            Symbol::Call {
+                name,
+                arguments,
+                range,
+            } => {
+                let err = match definitions.get(name) {
+                    None => ParseError::UndefinedMacro(*range),
+                    Some(definition) if definition.parameters.len() != arguments.len() => {
+                        ParseError::WrongArgumentCount(*range)
+                    }
+                    Some(_) => continue,
+                };
+                let start = |range: Range| (range.start_pos.line, range.start_pos.column);
+                let i = errors
+                    .iter()
+                    .position(|other| {
+                        other
+                            .range()
+                            .is_some_and(|other| start(other) > start(*range))
+                    })
+                    .unwrap_or(errors.len());
+                errors.insert(i, err);
+            }
+            Symbol::Definition { body, .. } => {
+                collect_definitions(std::slice::from_ref(symbol), definitions);
+                check_calls(body, definitions, errors);
+            }
+            Symbol::Conditional { body, .. } | Symbol::Loop { body, .. } => {
+                check_calls(body, definitions, errors)
+            }
+            _ => {}
+        }
+    }
+}
 
-    Spread { identifier: Identifier },
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub enum Condition {
+    Defined(Identifier),
+    Equals(Identifier, String),
 }
 
+/// Variables of a template. The ones given explicitly and the ones bound by the template itself
+/// (loop variables) are kept in the table, the rest are looked up with the resolver (if there is one)
+#[derive(Clone, Default)]
 pub struct SymbolTable {
-    variables: std::collections::HashMap<Identifier, String>,
+    variables: HashMap<Identifier, Value>,
+    resolver: Option<Arc<dyn Resolver>>,
 }
 
 impl SymbolTable {
     pub fn new<S: AsRef<str>>(variables: &[(S, S)]) -> SymbolTable {
-        let variables = variables
-            .iter()
-            .map(|(k, v)| (k.as_ref().to_string(), v.as_ref().to_string()))
-            .collect();
-        SymbolTable { variables }
+        SymbolTable::from_values(
+            variables
+                .iter()
+                .map(|(k, v)| (k.as_ref().to_string(), Value::from(v.as_ref()))),
+        )
+    }
+
+    pub fn from_values<I: IntoIterator<Item = (Identifier, Value)>>(variables: I) -> SymbolTable {
+        SymbolTable {
+            variables: variables.into_iter().collect(),
+            resolver: None,
+        }
+    }
+
+    /// Table where every variable is looked up with `resolver`
+    pub fn from_resolver<R: Resolver + 'static>(resolver: R) -> SymbolTable {
+        SymbolTable {
+            variables: HashMap::new(),
+            resolver: Some(Arc::new(resolver)),
+        }
+    }
+
+    /// Look up variables that are not defined otherwise in the environment
+    pub fn with_env(self) -> SymbolTable {
+        let resolver = match self.resolver {
+            Some(resolver) => ChainResolver::new().with(resolver).with(EnvResolver),
+            None => ChainResolver::new().with(EnvResolver),
+        };
+        SymbolTable {
+            variables: self.variables,
+            resolver: Some(Arc::new(resolver)),
+        }
+    }
+
+    /// Same table with `variables` defined too (replacing the ones with the same identifiers)
+    pub fn with_values<I: IntoIterator<Item = (Identifier, Value)>>(self, variables: I) -> Self {
+        let mut table = self;
+        table.variables.extend(variables);
+        table
+    }
+
+    /// Copy of the table where `identifier` is bound to `value`
+    pub fn bind(&self, identifier: &str, value: Value) -> SymbolTable {
+        let mut table = self.clone();
+        table.variables.insert(identifier.to_string(), value);
+        table
     }
 
+    // NOTE: only the root of a dotted identifier is checked, rest of the path is resolved when
+    // generating the text
     fn has_variable(&self, identifier: &str) -> bool {
-        self.variables.contains_key(identifier)
+        let root = identifier.split('.').next().unwrap_or(identifier);
+        self.get_value(root).is_some()
+    }
+
+    /// Value of the (possibly dotted) identifier
+    pub fn get_value(&self, identifier: &str) -> Option<Value> {
+        let mut path = identifier.split('.');
+        let root = path.next()?;
+        let value = match self.variables.get(root) {
+            Some(value) => Some(value.clone()),
+            None => self.resolver.as_ref()?.resolve_value(root),
+        }?;
+        value.get_path(path).cloned()
     }
 
-    // XXX: this is public so that we can use this for tests in the driver. Need to think of a
-    // better way to do this
-    #[cfg(test)]
     pub fn get_variable(&self, identifier: &str) -> Option<String> {
-        self.variables.get(identifier).cloned()
+        self.get_value(identifier).map(|value| value.to_string())
+    }
+}
+
+// Tables can be layered with other resolvers
+impl Resolver for SymbolTable {
+    fn resolve(&self, identifier: &str) -> Option<Cow<'_, str>> {
+        self.get_variable(identifier).map(Cow::Owned)
+    }
+
+    fn resolve_value(&self, identifier: &str) -> Option<Value> {
+        self.get_value(identifier)
+    }
+}
+
+// Range covering both `start_pos` and `end_pos`
+fn span(start_pos: &Position, end_pos: &Position) -> Range {
+    Range {
+        start_pos: *start_pos,
+        end_pos: Position {
+            line: end_pos.line,
+            column: end_pos.column + 1,
+        },
+    }
+}
+
+// Index of the first close delimiter in `tokens` that is on `line`
+fn closing_brace(tokens: &[Token], line: usize, delimiters: &Delimiters) -> Option<usize> {
+    let line_end = tokens
+        .iter()
+        .position(|token| token.start_pos().line != line)
+        .unwrap_or(tokens.len());
+    (0..line_end).find(|&i| delimiters.closes(&tokens[i..line_end]))
+}
+
+// Source text of `tokens` (all in the same line) spanning from `start_column` to `end_column`
+fn text_between(tokens: &[Token], start_column: usize, end_column: usize) -> String {
+    let mut parts = vec![];
+    let mut current_column = start_column;
+    for token in tokens {
+        let column = token.start_pos().column;
+        parts.push(" ".repeat(column - current_column));
+        let part = token.to_string();
+        current_column = column + part.chars().count();
+        parts.push(part);
+    }
+    parts.push(" ".repeat(end_column - current_column));
+    parts.concat()
+}
+
+// Whether `tokens` starts with a `${<keyword>` tag
+pub(crate) fn is_block_tag(tokens: &[Token], keyword: &str, delimiters: &Delimiters) -> bool {
+    delimiters.opens(tokens)
+        && matches!(tokens.get(delimiters.open.len()), Some(Token::Word { text, .. }) if text == keyword)
+}
+
+// Index of the `${<close_keyword>}` tag closing the block the `tokens` are in
+fn find_block_end(
+    tokens: &[Token],
+    open_keyword: &str,
+    close_keyword: &str,
+    delimiters: &Delimiters,
+) -> Option<usize> {
+    let mut depth = 0;
+    for i in 0..tokens.len() {
+        if is_block_tag(&tokens[i..], open_keyword, delimiters) {
+            depth += 1;
+        } else if is_block_tag(&tokens[i..], close_keyword, delimiters)
+            && delimiters.closes(&tokens[i + delimiters.open.len() + 1..])
+        {
+            if depth == 0 {
+                return Some(i);
+            }
+            depth -= 1;
+        }
     }
+    None
 }
 
-pub fn parse_tokens(tokens: &[Token], symbols: &SymbolTable) -> Result<Vec<Symbol>, ParseError> {
+// Filter names of a `|filter1|filter2` sequence
+fn parse_filters(tokens: &[Token]) -> Option<Vec<Identifier>> {
+    tokens
+        .chunks(2)
+        .map(|chunk| match chunk {
+            [Token::Punctuation { value: '|', .. }, Token::Word { text, .. }] => {
+                Some(text.to_string())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// Identifier made of `.` separated names at the start of `tokens`, along with the rest of the tokens
+fn parse_identifier(tokens: &[Token]) -> Option<(Identifier, &[Token])> {
+    let (name, rest) = parse_name(tokens)?;
+    match rest {
+        [Token::Punctuation { value: '.', .. }, path @ ..] if parse_name(path).is_some() => {
+            let (path, rest) = parse_identifier(path)?;
+            Some((format!("{}.{}", name, path), rest))
+        }
+        _ => Some((name, rest)),
+    }
+}
+
+// Name made of words and `_`s with nothing between them (`_` is punctuation for the tokenizer)
+fn parse_name(tokens: &[Token]) -> Option<(String, &[Token])> {
+    let is_part = |token: &Token| {
+        matches!(
+            token,
+            Token::Word { .. } | Token::Punctuation { value: '_', .. }
+        )
+    };
+    let length = tokens
+        .iter()
+        .enumerate()
+        .take_while(|(i, token)| {
+            is_part(token) && (*i == 0 || tokens[i - 1].range().end_pos == token.start_pos())
+        })
+        .count();
+    if length == 0 {
+        return None;
+    }
+    let name = tokens[..length].iter().map(Token::to_string).collect();
+    Some((name, &tokens[length..]))
+}
+
+// Symbol for the content of a `${...}` placeholder (`None` if it is not a valid placeholder)
+fn parse_placeholder(
+    tokens: &[Token],
+    start_pos: &Position,
+    end_pos: &Position,
+    symbols: Option<&SymbolTable>,
+) -> Result<Option<Symbol>, ParseError> {
+    let range = span(start_pos, end_pos);
     match tokens {
-        [] => Ok(vec![]),
-        [Token::Word { text, .. }, rest @ ..] => Ok(vec![Symbol::Word {
-            text: text.to_string(),
-        }]
-        .into_iter()
-        .chain(parse_tokens(rest, symbols)?)
-        .collect()),
-        [Token::Punctuation {
-            value: '$',
-            pos: start_pos,
-        }, Token::Punctuation { value: '{', .. }, Token::Word {
-            text: identifier, ..
-        }, Token::Punctuation {
-            value: '}',
-            pos: end_pos,
-        }, rest @ ..] => {
-            if symbols.has_variable(identifier) {
-                Ok(vec![Symbol::Replace {
-                    identifier: identifier.to_string(),
-                }]
-                .into_iter()
-                .chain(parse_tokens(rest, symbols)?)
-                .collect())
-            } else {
-                Err(ParseError::VariableNotFound((start_pos, end_pos).into()))
+        [Token::Punctuation { value: '.', .. }, Token::Punctuation { value: '.', .. }, Token::Punctuation { value: '.', .. }, rest @ ..] => {
+            match parse_identifier(rest) {
+                Some((identifier, selection))
+                    if selection.is_empty()
+                        || matches!(selection[0], Token::Punctuation { value: ':', .. }) =>
+                {
+                    let lines = match selection {
+                        [] => None,
+                        [_, selection @ ..] => Some(
+                            parse_line_range(selection)
+                                .ok_or(ParseError::InvalidLineRange(range))?,
+                        ),
+                    };
+                    if is_defined(symbols, &identifier) {
+                        Ok(Some(Symbol::Spread {
+                            identifier,
+                            lines,
+                            range,
+                        }))
+                    } else {
+                        Err(ParseError::FileNotFound(range))
+                    }
+                }
+                _ => Ok(None),
+            }
+        }
+        _ => match parse_identifier(tokens) {
+            Some((
+                name,
+                [Token::Punctuation {
+                    value: '(',
+                    pos: open_pos,
+                }, arguments @ .., Token::Punctuation {
+                    value: ')',
+                    pos: close_pos,
+                }],
+            )) => {
+                let arguments = text_between(arguments, open_pos.column + 1, close_pos.column);
+                let arguments = if arguments.trim().is_empty() {
+                    vec![]
+                } else {
+                    arguments
+                        .split(',')
+                        .map(|argument| argument.trim().to_string())
+                        .collect()
+                };
+                Ok(Some(Symbol::Call {
+                    name,
+                    arguments,
+                    range,
+                }))
+            }
+            Some((
+                identifier,
+                [Token::Punctuation { value: ':', .. }, Token::Punctuation {
+                    value: '-',
+                    pos: default_start,
+                }, rest @ ..],
+            )) => Ok(Some(Symbol::Replace {
+                identifier,
+                default: Some(text_between(rest, default_start.column + 1, end_pos.column)),
+                format: None,
+                filters: vec![],
+                range,
+            })),
+            Some((identifier, [Token::Punctuation { value: ':', pos }, rest @ ..]))
+                if takes_format(&identifier) && !rest.is_empty() =>
+            {
+                let format = text_between(rest, pos.column + 1, end_pos.column);
+                if !is_valid_format(&format) {
+                    return Err(ParseError::InvalidFormat(range));
+                }
+                Ok(Some(Symbol::Replace {
+                    identifier,
+                    default: None,
+                    format: Some(format),
+                    filters: vec![],
+                    range,
+                }))
+            }
+            Some((identifier, rest)) if rest.is_empty() || is_filter_separator(&rest[0]) => {
+                let filters = parse_filters(rest).ok_or(ParseError::InvalidFilter(range))?;
+                if is_defined(symbols, &identifier) || is_builtin(&identifier) {
+                    Ok(Some(Symbol::Replace {
+                        identifier,
+                        default: None,
+                        format: None,
+                        filters,
+                        range,
+                    }))
+                } else {
+                    Err(ParseError::VariableNotFound(range))
+                }
+            }
+            _ => Ok(None),
+        },
+    }
+}
+
+// `name(parameter, ...)` of a macro definition
+fn parse_signature(tokens: &[Token]) -> Option<(Identifier, Vec<Identifier>)> {
+    let (name, rest) = parse_name(tokens)?;
+    let parameters = match rest {
+        [Token::Punctuation { value: '(', .. }, Token::Punctuation { value: ')', .. }] => vec![],
+        [Token::Punctuation { value: '(', .. }, parameters @ .., Token::Punctuation { value: ')', .. }] => {
+            parameters
+                .split(|token| matches!(token, Token::Punctuation { value: ',', .. }))
+                .map(|parameter| match parse_name(parameter) {
+                    Some((parameter, [])) => Some(parameter),
+                    _ => None,
+                })
+                .collect::<Option<Vec<Identifier>>>()?
+        }
+        _ => return None,
+    };
+    Some((name, parameters))
+}
+
+// `start-end` or `start-` where the lines start from 1
+fn parse_line_range(tokens: &[Token]) -> Option<LineRange> {
+    let line = |text: &str| text.parse::<usize>().ok().filter(|line| *line > 0);
+    match tokens {
+        [Token::Word { text: start, .. }, Token::Punctuation { value: '-', .. }] => {
+            Some(LineRange {
+                start: line(start)?,
+                end: None,
+            })
+        }
+        [Token::Word { text: start, .. }, Token::Punctuation { value: '-', .. }, Token::Word { text: end, .. }] =>
+        {
+            let (start, end) = (line(start)?, line(end)?);
+            (start <= end).then_some(LineRange {
+                start,
+                end: Some(end),
+            })
+        }
+        _ => None,
+    }
+}
+
+// Existence of variables is only checked when there is a table to check against
+fn is_defined(symbols: Option<&SymbolTable>, identifier: &str) -> bool {
+    symbols.is_none_or(|symbols| symbols.has_variable(identifier))
+}
+
+fn is_filter_separator(token: &Token) -> bool {
+    matches!(token, Token::Punctuation { value: '|', .. })
+}
+
+fn parse_condition(tokens: &[Token], range: &Range) -> Result<Condition, ParseError> {
+    match parse_identifier(tokens) {
+        Some((identifier, [])) => Ok(Condition::Defined(identifier)),
+        Some((
+            identifier,
+            [Token::Punctuation { value: '=', .. }, Token::Punctuation { value: '=', .. }, value @ ..],
+        )) if !value.is_empty() => {
+            let start_column = value[0].start_pos().column;
+            let end_column = value[value.len() - 1].range().end_pos.column;
+            Ok(Condition::Equals(
+                identifier,
+                text_between(value, start_column, end_column),
+            ))
+        }
+        _ => Err(ParseError::InvalidCondition(*range)),
+    }
+}
+
+/// Symbols of the template. Variables are not looked up here (missing ones are reported when the
+/// text is generated) so any template can be parsed without knowing its variables
+pub fn parse_tokens(tokens: &[Token]) -> Result<Vec<Symbol>, ParseError> {
+    let (symbols, errors) = parse_tokens_with_errors(tokens);
+    match errors.into_iter().next() {
+        Some(err) => Err(err),
+        None => Ok(symbols),
+    }
+}
+
+/// Symbols of the parts of the template that are valid, along with every syntax error
+pub fn parse_tokens_with_errors(tokens: &[Token]) -> (Vec<Symbol>, Vec<ParseError>) {
+    parse_tokens_with_delimiters(tokens, &Delimiters::default())
+}
+
+/// Same as `parse_tokens_with_errors` for templates whose tags are between `delimiters`
+pub fn parse_tokens_with_delimiters(
+    tokens: &[Token],
+    delimiters: &Delimiters,
+) -> (Vec<Symbol>, Vec<ParseError>) {
+    let mut errors = vec![];
+    let symbols = parse_symbols(tokens, None, delimiters, 0, &mut errors);
+    (symbols, errors)
+}
+
+/// Every problem in the template (in the order they appear) including the variables that are not
+/// defined in `symbols`
+pub fn check_tokens(tokens: &[Token], symbols: &SymbolTable) -> Vec<ParseError> {
+    check_tokens_with_delimiters(tokens, symbols, &Delimiters::default())
+}
+
+/// Same as `check_tokens` for templates whose tags are between `delimiters`
+pub fn check_tokens_with_delimiters(
+    tokens: &[Token],
+    symbols: &SymbolTable,
+    delimiters: &Delimiters,
+) -> Vec<ParseError> {
+    let mut errors = vec![];
+    let parsed = parse_symbols(tokens, Some(symbols), delimiters, 0, &mut errors);
+    check_calls(&parsed, &mut HashMap::new(), &mut errors);
+    errors
+}
+
+// Blocks nested deeper than this are an error, since the bodies are parsed (and rendered)
+// recursively
+const MAX_NESTING_DEPTH: usize = 256;
+
+// Errors are collected in `errors` and parsing resumes after the offending tag. Only the bodies
+// of blocks are parsed recursively, `depth` is the number of blocks `tokens` are in
+fn parse_symbols(
+    tokens: &[Token],
+    symbols: Option<&SymbolTable>,
+    delimiters: &Delimiters,
+    depth: usize,
+    errors: &mut Vec<ParseError>,
+) -> Vec<Symbol> {
+    let mut output = vec![];
+    let mut tokens = tokens;
+    while !tokens.is_empty() {
+        if delimiters.opens(tokens) {
+            if let Some(rest) = parse_tag(tokens, symbols, delimiters, depth, &mut output, errors) {
+                tokens = rest;
+                continue;
+            }
+        }
+        tokens = match tokens {
+            [] => break,
+            [Token::Word { text, range } | Token::Whitespace { text, range }, rest @ ..] => {
+                output.push(Symbol::Word {
+                    text: text.to_string(),
+                    range: *range,
+                });
+                rest
+            }
+            // `\${` is written out as a literal `${`
+            [Token::Punctuation {
+                value: '\\',
+                pos: escape_pos,
+            }, rest @ ..]
+                if delimiters.opens(rest)
+                    && escape_pos.line == rest[0].start_pos().line
+                    && escape_pos.column + 1 == rest[0].start_pos().column =>
+            {
+                output.push(Symbol::Word {
+                    text: delimiters.open[0].to_string(),
+                    range: span(escape_pos, &rest[0].start_pos()),
+                });
+                &rest[1..]
+            }
+            [Token::Punctuation { value, pos }, rest @ ..] => {
+                output.push(Symbol::Word {
+                    text: value.to_string(),
+                    range: span(pos, pos),
+                });
+                rest
+            }
+        };
+    }
+    output
+}
+
+// Symbol of the tag `tokens` start with (pushed to `output`) along with the tokens after it, `None`
+// if the open delimiter is just text
+fn parse_tag<'a>(
+    tokens: &'a [Token],
+    symbols: Option<&SymbolTable>,
+    delimiters: &Delimiters,
+    depth: usize,
+    output: &mut Vec<Symbol>,
+    errors: &mut Vec<ParseError>,
+) -> Option<&'a [Token]> {
+    let start_pos = tokens[0].start_pos();
+    let start_pos = &start_pos;
+    // tokens of a `${endif}` like tag
+    let end_tag_length = delimiters.open.len() + 1 + delimiters.close.len();
+    let closing = |tokens: &[Token]| closing_brace(tokens, start_pos.line, delimiters);
+    // position of the last character of the close delimiter starting at `close`
+    let close_pos =
+        |tokens: &[Token], close: usize| tokens[close + delimiters.close.len() - 1].start_pos();
+    // the whole block is left out when it's too deep
+    let too_deep = |open_range: Range, errors: &mut Vec<ParseError>| {
+        let too_deep = depth == MAX_NESTING_DEPTH;
+        if too_deep {
+            errors.push(ParseError::NestingTooDeep(open_range));
+        }
+        too_deep
+    };
+    let rest = &tokens[delimiters.open.len()..];
+    match rest {
+        [Token::Word { text: keyword, .. }, rest @ ..]
+            if keyword == "if" && closing(rest).is_some() =>
+        {
+            let close = closing(rest).unwrap();
+            let open_range = span(start_pos, &close_pos(rest, close));
+            let condition =
+                parse_condition(&rest[..close], &open_range).map_err(|err| errors.push(err));
+            let rest = &rest[close + delimiters.close.len()..];
+            let end = match find_block_end(rest, "if", "endif", delimiters) {
+                Some(end) => end,
+                None => {
+                    errors.push(ParseError::UnclosedBlock(open_range));
+                    return Some(rest);
+                }
+            };
+            let close_range = span(
+                &rest[end].start_pos(),
+                &rest[end + end_tag_length - 1].start_pos(),
+            );
+            if too_deep(open_range, errors) {
+                return Some(&rest[end + end_tag_length..]);
+            }
+            let body = parse_symbols(&rest[..end], symbols, delimiters, depth + 1, errors);
+            if let Ok(condition) = condition {
+                output.push(Symbol::Conditional {
+                    condition,
+                    body,
+                    open_range,
+                    close_range,
+                });
+            }
+            Some(&rest[end + end_tag_length..])
+        }
+        [Token::Word { text: keyword, .. }, rest @ ..]
+            if keyword == "for" && closing(rest).is_some() =>
+        {
+            let close = closing(rest).unwrap();
+            let open_range = span(start_pos, &close_pos(rest, close));
+            let header = match &rest[..close] {
+                [Token::Word { text: variable, .. }, Token::Word { text: keyword, .. }, iterable @ ..]
+                    if keyword == "in" =>
+                {
+                    match parse_identifier(iterable) {
+                        Some((iterable, [])) => Ok((variable.to_string(), iterable)),
+                        _ => Err(ParseError::InvalidLoop(open_range)),
+                    }
+                }
+                _ => Err(ParseError::InvalidLoop(open_range)),
+            };
+            let rest = &rest[close + delimiters.close.len()..];
+            let (variable, iterable) = match header {
+                Ok(header) => header,
+                Err(err) => {
+                    // the body can't be checked without knowing the loop variable
+                    errors.push(err);
+                    return match find_block_end(rest, "for", "endfor", delimiters) {
+                        Some(end) => Some(&rest[end + end_tag_length..]),
+                        None => Some(rest),
+                    };
+                }
+            };
+            if !is_defined(symbols, &iterable) {
+                errors.push(ParseError::VariableNotFound(open_range));
             }
+            let end = match find_block_end(rest, "for", "endfor", delimiters) {
+                Some(end) => end,
+                None => {
+                    errors.push(ParseError::UnclosedBlock(open_range));
+                    return Some(rest);
+                }
+            };
+            let close_range = span(
+                &rest[end].start_pos(),
+                &rest[end + end_tag_length - 1].start_pos(),
+            );
+            if too_deep(open_range, errors) {
+                return Some(&rest[end + end_tag_length..]);
+            }
+            // the body is parsed as if the loop variable is defined
+            let body_symbols = symbols.map(|symbols| symbols.bind(&variable, Value::from("")));
+            let body = parse_symbols(
+                &rest[..end],
+                body_symbols.as_ref(),
+                delimiters,
+                depth + 1,
+                errors,
+            );
+            output.push(Symbol::Loop {
+                variable,
+                iterable,
+                body,
+                open_range,
+                close_range,
+            });
+            Some(&rest[end + end_tag_length..])
         }
-        [Token::Punctuation {
-            value: '$',
-            pos: start_pos,
-        }, Token::Punctuation { value: '{', .. }, Token::Punctuation { value: '.', .. }, Token::Punctuation { value: '.', .. }, Token::Punctuation { value: '.', .. }, Token::Word {
-            text: identifier, ..
-        }, Token::Punctuation {
-            value: '}',
-            pos: end_pos,
-        }, rest @ ..] => {
-            if symbols.has_variable(identifier) {
-                // TODO: check if the identifier is valid
-                Ok(vec![Symbol::Spread {
-                    identifier: identifier.to_string(),
-                }]
-                .into_iter()
-                .chain(parse_tokens(rest, symbols)?)
-                .collect())
-            } else {
-                Err(ParseError::FileNotFound((start_pos, end_pos).into()))
+        [Token::Word { text: keyword, .. }, rest @ ..]
+            if keyword == "def" && closing(rest).is_some() =>
+        {
+            let close = closing(rest).unwrap();
+            let open_range = span(start_pos, &close_pos(rest, close));
+            let signature =
+                parse_signature(&rest[..close]).ok_or(ParseError::InvalidDefinition(open_range));
+            let rest = &rest[close + delimiters.close.len()..];
+            let (name, parameters) = match signature {
+                Ok(signature) => signature,
+                Err(err) => {
+                    errors.push(err);
+                    return match find_block_end(rest, "def", "enddef", delimiters) {
+                        Some(end) => Some(&rest[end + end_tag_length..]),
+                        None => Some(rest),
+                    };
+                }
+            };
+            let end = match find_block_end(rest, "def", "enddef", delimiters) {
+                Some(end) => end,
+                None => {
+                    errors.push(ParseError::UnclosedBlock(open_range));
+                    return Some(rest);
+                }
+            };
+            let close_range = span(
+                &rest[end].start_pos(),
+                &rest[end + end_tag_length - 1].start_pos(),
+            );
+            if too_deep(open_range, errors) {
+                return Some(&rest[end + end_tag_length..]);
+            }
+            // the body is parsed as if the parameters are defined
+            let body_symbols = symbols.map(|symbols| {
+                parameters.iter().fold(symbols.clone(), |table, parameter| {
+                    table.bind(parameter, Value::from(""))
+                })
+            });
+            let body = parse_symbols(
+                &rest[..end],
+                body_symbols.as_ref(),
+                delimiters,
+                depth + 1,
+                errors,
+            );
+            output.push(Symbol::Definition {
+                name,
+                parameters,
+                body,
+                open_range,
+                close_range,
+            });
+            Some(&rest[end + end_tag_length..])
+        }
+        [Token::Word { text: keyword, .. }, rest @ ..]
+            if (keyword == "endif" || keyword == "endfor" || keyword == "enddef")
+                && delimiters.closes(rest) =>
+        {
+            let end_pos = rest[delimiters.close.len() - 1].start_pos();
+            errors.push(ParseError::UnexpectedBlockEnd(span(start_pos, &end_pos)));
+            Some(&rest[delimiters.close.len()..])
+        }
+        [Token::Punctuation { value: '#', pos }, rest @ ..] if closing(rest).is_some() => {
+            let close = closing(rest).unwrap();
+            let end_pos = close_pos(rest, close);
+            let text = text_between(
+                &rest[..close],
+                pos.column + 1,
+                rest[close].start_pos().column,
+            );
+            output.push(Symbol::Comment {
+                text: text.trim().to_string(),
+                range: span(start_pos, &end_pos),
+            });
+            Some(&rest[close + delimiters.close.len()..])
+        }
+        _ => {
+            let close = closing(rest)?;
+            let end_pos = close_pos(rest, close);
+            let after = &rest[close + delimiters.close.len()..];
+            match parse_placeholder(&rest[..close], start_pos, &end_pos, symbols) {
+                Ok(Some(symbol)) => {
+                    output.push(symbol);
+                    Some(after)
+                }
+                // not a placeholder so the open delimiter is just text
+                Ok(None) => {
+                    output.push(Symbol::Word {
+                        text: delimiters.open[0].to_string(),
+                        range: span(start_pos, start_pos),
+                    });
+                    Some(&tokens[1..])
+                }
+                Err(err) => {
+                    errors.push(err);
+                    Some(after)
+                }
             }
         }
-        [Token::Punctuation { value, .. }, rest @ ..] => Ok(vec![Symbol::Word {
-            text: value.to_string(),
-        }]
-        .into_iter()
-        .chain(parse_tokens(rest, symbols)?)
-        .collect()),
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
 
-    use crate::front::{create_tokens, reconstruct_text, ParseError, Symbol, SymbolTable};
+    use crate::front::{
+        check_tokens, create_tokens, parse_tokens_with_delimiters, parse_tokens_with_errors,
+        reconstruct_text, tokenize_reader, tokenize_reader_with_errors, tokenize_text,
+        variable_uses, Condition, Delimiters, ParseError, Symbol, SymbolTable, Token, Tokens,
+        VariableKind,
+    };
 
-    use super::{parse_tokens, Position, Range};
+    use super::{parse_tokens, LineRange, Position, Range};
+    use crate::value::Value;
 
     #[test]
     fn test_tokenize_simple_line() {
         let l = "Hello world!".to_string();
         let tokens = create_tokens(l, 0).unwrap();
-        let expected = vec![
+        let expected = [
             create_word("Hello", 0),
             create_word("world", "Hello".len() + 1),
             create_punctuation("!", "Hello world".len()),
@@ -312,24 +1540,86 @@ mod tests {
         assert_eq!(r, l);
     }
 
+    #[test]
+    fn test_tokenize_whitespace() {
+        let tokens = create_tokens("\tfoo \t\t${bar}".to_string(), 0).unwrap();
+        assert_eq!(tokens.len(), 7);
+        assert_eq!(
+            tokens[2],
+            Token::Whitespace {
+                text: "\t\t".to_string(),
+                range: create_range(5, 7)
+            }
+        );
+        let text = "all:\n\t$(CC)\t-o ${out}\x0cx ";
+        assert_eq!(reconstruct_text(&tokenize_text(text).unwrap()), text);
+        // spaces ending a line are kept
+        let text = "a \t  \n   \nb";
+        let tokens = tokenize_text(text).unwrap();
+        assert_eq!(
+            tokens[2],
+            Token::Whitespace {
+                text: "  ".to_string(),
+                range: create_range(3, 5)
+            }
+        );
+        assert_eq!(reconstruct_text(&tokens), text);
+    }
+
+    #[test]
+    fn test_roundtrip_unicode() {
+        let text = "héllo 🎉 ${name}, wörld!\n  日本語 ${x:-ä b}";
+        let tokens = tokenize_text(text).unwrap();
+        assert_eq!(tokens[1].range(), create_range(6, 7));
+        assert_eq!(reconstruct_text(&tokens), text);
+    }
+
+    #[test]
+    fn test_roundtrip_line_ending_in_word() {
+        let l = "Hello, world".to_string();
+        let tokens = create_tokens(l.clone(), 0).unwrap();
+        assert_eq!(reconstruct_text(&tokens), l);
+    }
+
+    #[test]
+    fn test_tokens_are_lazy() {
+        let text = "Hello world!\n\t${var1}";
+        let tokens: Vec<Token> = Tokens::from_text(text).map(Result::unwrap).collect();
+        assert_eq!(tokens, tokenize_text(text).unwrap());
+        // lines are only read when their tokens are needed
+        let mut tokens = Tokens::from_reader(&b"a b\n\xff"[..]);
+        assert!(matches!(tokens.next(), Some(Ok(Token::Word { .. }))));
+        assert!(matches!(tokens.next(), Some(Ok(Token::Word { .. }))));
+        assert!(matches!(tokens.next(), Some(Ok(token)) if token.is_line_break()));
+        assert_eq!(tokens.next(), Some(Err(ParseError::FailedToReadLine(1))));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn test_tokenize_reader() {
+        let text = "Hello world!\n  ${var1}";
+        let tokens = tokenize_reader(std::io::Cursor::new(text)).unwrap();
+        assert_eq!(tokens, tokenize_text(text).unwrap());
+        assert_eq!(reconstruct_text(&tokens), text);
+    }
+
     #[test]
     fn test_parsing_just_text() {
-        let symbols = parse_tokens(
-            &create_tokens("Hello world!".to_string(), 0).unwrap(),
-            &SymbolTable::new::<&str>(&[]),
-        )
-        .unwrap();
+        let symbols = parse_tokens(&create_tokens("Hello world!".to_string(), 0).unwrap()).unwrap();
         assert_eq!(
             symbols,
             vec![
                 Symbol::Word {
-                    text: "Hello".to_string()
+                    text: "Hello".to_string(),
+                    range: create_range(0, 5)
                 },
                 Symbol::Word {
-                    text: "world".to_string()
+                    text: "world".to_string(),
+                    range: create_range(6, 11)
                 },
                 Symbol::Word {
-                    text: "!".to_string()
+                    text: "!".to_string(),
+                    range: create_range(11, 12)
                 }
             ]
         );
@@ -337,33 +1627,465 @@ mod tests {
 
     #[test]
     fn test_parsing_replace() {
-        let symbols = parse_tokens(
-            &create_tokens("Hello ${var1}! ${var2}".to_string(), 0).unwrap(),
-            &SymbolTable::new(&[("var1", ""), ("var2", "")]),
+        let symbols =
+            parse_tokens(&create_tokens("Hello ${var1}! ${var2}".to_string(), 0).unwrap()).unwrap();
+        assert_eq!(
+            symbols,
+            vec![
+                Symbol::Word {
+                    text: "Hello".to_string(),
+                    range: create_range(0, 5)
+                },
+                Symbol::Replace {
+                    identifier: "var1".to_string(),
+                    default: None,
+                    format: None,
+                    filters: vec![],
+                    range: create_range(6, 13)
+                },
+                Symbol::Word {
+                    text: "!".to_string(),
+                    range: create_range(13, 14)
+                },
+                Symbol::Replace {
+                    identifier: "var2".to_string(),
+                    default: None,
+                    format: None,
+                    filters: vec![],
+                    range: create_range(15, 22)
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parsing_replace_with_default() {
+        let symbols =
+            parse_tokens(&create_tokens("${var1:-some value!} ${var2:-}".to_string(), 0).unwrap())
+                .unwrap();
+        assert_eq!(
+            symbols,
+            vec![
+                Symbol::Replace {
+                    identifier: "var1".to_string(),
+                    default: Some("some value!".to_string()),
+                    format: None,
+                    filters: vec![],
+                    range: create_range(0, 20)
+                },
+                Symbol::Replace {
+                    identifier: "var2".to_string(),
+                    default: Some("".to_string()),
+                    format: None,
+                    filters: vec![],
+                    range: create_range(21, 30)
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_symbol_table_env_fallback() {
+        std::env::set_var("PTEMPLATOR_TEST_ENV_VAR", "from env");
+        let symbols = SymbolTable::new(&[("var1", "explicit")]);
+        assert_eq!(symbols.get_variable("PTEMPLATOR_TEST_ENV_VAR"), None);
+        let symbols = symbols.with_env();
+        assert_eq!(
+            symbols.get_variable("PTEMPLATOR_TEST_ENV_VAR"),
+            Some("from env".to_string())
+        );
+        assert_eq!(symbols.get_variable("var1"), Some("explicit".to_string()));
+    }
+
+    #[test]
+    fn test_symbol_table_resolver() {
+        let config = Value::Map([("host".to_string(), Value::from("localhost"))].into());
+        let resolver = HashMap::from([
+            ("var1".to_string(), Value::from("resolved")),
+            ("config".to_string(), config),
+        ]);
+        let symbols = SymbolTable::from_resolver(resolver);
+        assert_eq!(symbols.get_variable("var1"), Some("resolved".to_string()));
+        assert_eq!(
+            symbols.get_variable("config.host"),
+            Some("localhost".to_string())
+        );
+        // bound variables shadow the resolved ones
+        let symbols = symbols.bind("var1", Value::from("bound"));
+        assert_eq!(symbols.get_variable("var1"), Some("bound".to_string()));
+        assert_eq!(symbols.get_variable("var2"), None);
+    }
+
+    #[test]
+    fn test_parsing_builtin_format() {
+        let tokens = create_tokens("${__date:%d/%m} ${__time:%q}".to_string(), 0).unwrap();
+        let symbols = SymbolTable::new::<&str>(&[]);
+        let (parsed, errors) = parse_tokens_with_errors(&tokens);
+        assert_eq!(
+            parsed,
+            vec![Symbol::Replace {
+                identifier: "__date".to_string(),
+                default: None,
+                format: Some("%d/%m".to_string()),
+                filters: vec![],
+                range: create_range(0, 15)
+            }]
+        );
+        assert_eq!(
+            errors,
+            vec![ParseError::InvalidFormat(create_range(16, 28))]
+        );
+        // builtin variables are always defined
+        assert_eq!(check_tokens(&tokens[..7], &symbols), vec![]);
+    }
+
+    #[test]
+    fn test_parsing_comment() {
+        let symbols =
+            parse_tokens(&create_tokens("a${#  note: ${x} }".to_string(), 0).unwrap()).unwrap();
+        assert_eq!(
+            symbols,
+            vec![
+                Symbol::Word {
+                    text: "a".to_string(),
+                    range: create_range(0, 1)
+                },
+                Symbol::Comment {
+                    text: "note: ${x".to_string(),
+                    range: create_range(1, 16)
+                },
+                Symbol::Word {
+                    text: "}".to_string(),
+                    range: create_range(17, 18)
+                },
+            ]
+        );
+        assert!(variable_uses(&symbols).is_empty());
+    }
+
+    #[test]
+    fn test_parsing_macros() {
+        let tokens = create_tokens(
+            "${def greet(name, greeting)}${greeting} ${name}${enddef}${greet(a b, hi)}".to_string(),
+            0,
         )
         .unwrap();
+        let symbols = parse_tokens(&tokens).unwrap();
+        assert!(matches!(
+            symbols.as_slice(),
+            [
+                Symbol::Definition { name, parameters, body, .. },
+                Symbol::Call { arguments, range, .. }
+            ] if name == "greet"
+                && parameters == &["name", "greeting"]
+                && body.len() == 2
+                && arguments == &["a b", "hi"]
+                && *range == create_range(56, 73)
+        ));
+        // parameters are not variables of the template
+        assert!(variable_uses(&symbols).is_empty());
+        let errors = check_tokens(
+            &tokenize_text(
+                "${def f(x)}${x}${enddef}
+${f()}
+${g()} ${def h(}${enddef} ${def g()}${enddef}",
+            )
+            .unwrap(),
+            &SymbolTable::new::<&str>(&[]),
+        );
+        assert!(matches!(
+            errors.as_slice(),
+            [
+                ParseError::WrongArgumentCount(_),
+                ParseError::UndefinedMacro(_),
+                ParseError::InvalidDefinition(_)
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_parsing_escaped_replace() {
+        let symbols = parse_tokens(&create_tokens("\\${var1}".to_string(), 0).unwrap()).unwrap();
         assert_eq!(
             symbols,
             vec![
                 Symbol::Word {
-                    text: "Hello".to_string()
+                    text: "$".to_string(),
+                    range: create_range(0, 2)
                 },
-                Symbol::Replace {
-                    identifier: "var1".to_string()
+                Symbol::Word {
+                    text: "{".to_string(),
+                    range: create_range(2, 3)
                 },
                 Symbol::Word {
-                    text: "!".to_string()
+                    text: "var1".to_string(),
+                    range: create_range(3, 7)
+                },
+                Symbol::Word {
+                    text: "}".to_string(),
+                    range: create_range(7, 8)
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parsing_custom_delimiters() {
+        let delimiters = Delimiters::new("{{", "}}").unwrap();
+        let tokens = tokenize_text("echo ${HOME} {{var1}}\n{{if var2}}x{{endif}} \\{{").unwrap();
+        let (symbols, errors) = parse_tokens_with_delimiters(&tokens, &delimiters);
+        assert!(errors.is_empty());
+        let text: Vec<&Symbol> = symbols
+            .iter()
+            .filter(|symbol| !matches!(symbol, Symbol::Word { .. }))
+            .collect();
+        assert_eq!(
+            text,
+            vec![
+                &Symbol::Replace {
+                    identifier: "var1".to_string(),
+                    default: None,
+                    format: None,
+                    filters: vec![],
+                    range: create_range(13, 21)
+                },
+                &Symbol::Conditional {
+                    condition: Condition::Defined("var2".to_string()),
+                    body: vec![Symbol::Word {
+                        text: "x".to_string(),
+                        range: Range {
+                            start_pos: Position {
+                                line: 1,
+                                column: 11
+                            },
+                            end_pos: Position {
+                                line: 1,
+                                column: 12
+                            }
+                        }
+                    }],
+                    open_range: Range {
+                        start_pos: Position { line: 1, column: 0 },
+                        end_pos: Position {
+                            line: 1,
+                            column: 11
+                        }
+                    },
+                    close_range: Range {
+                        start_pos: Position {
+                            line: 1,
+                            column: 12
+                        },
+                        end_pos: Position {
+                            line: 1,
+                            column: 21
+                        }
+                    }
+                }
+            ]
+        );
+        // `${` is just text and `\{{` is a literal `{{`
+        let words: String = symbols
+            .iter()
+            .filter_map(|symbol| match symbol {
+                Symbol::Word { text, .. } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(words, "echo${HOME}\n{{");
+        let (_, errors) =
+            parse_tokens_with_delimiters(&tokenize_text("{{endfor}} {{if}}").unwrap(), &delimiters);
+        assert!(matches!(
+            errors.as_slice(),
+            [
+                ParseError::UnexpectedBlockEnd(_),
+                ParseError::InvalidCondition(_),
+                ParseError::UnclosedBlock(_)
+            ]
+        ));
+        assert_eq!(Delimiters::new("{{", ""), None);
+        assert_eq!(Delimiters::new("<%", "a"), None);
+        assert_eq!(Delimiters::new("\\{", "}"), None);
+    }
+
+    #[test]
+    fn test_parsing_conditional() {
+        let symbols = parse_tokens(
+            &create_tokens(
+                "${if var1 == a b}x${if var2}y${endif}${endif}!".to_string(),
+                0,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            symbols,
+            vec![
+                Symbol::Conditional {
+                    condition: Condition::Equals("var1".to_string(), "a b".to_string()),
+                    body: vec![
+                        Symbol::Word {
+                            text: "x".to_string(),
+                            range: create_range(17, 18)
+                        },
+                        Symbol::Conditional {
+                            condition: Condition::Defined("var2".to_string()),
+                            body: vec![Symbol::Word {
+                                text: "y".to_string(),
+                                range: create_range(28, 29)
+                            }],
+                            open_range: create_range(18, 28),
+                            close_range: create_range(29, 37)
+                        }
+                    ],
+                    open_range: create_range(0, 17),
+                    close_range: create_range(37, 45)
                 },
+                Symbol::Word {
+                    text: "!".to_string(),
+                    range: create_range(45, 46)
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parsing_conditional_err() {
+        let unclosed = parse_tokens(&tokenize_text("${if var1}\nx").unwrap());
+        assert!(matches!(unclosed, Err(ParseError::UnclosedBlock(_))));
+        let unexpected = parse_tokens(&tokenize_text("x${endif}").unwrap());
+        assert!(matches!(unexpected, Err(ParseError::UnexpectedBlockEnd(_))));
+        let invalid = parse_tokens(&tokenize_text("${if a b}${endif}").unwrap());
+        assert!(matches!(invalid, Err(ParseError::InvalidCondition(_))));
+    }
+
+    #[test]
+    fn test_parsing_loop() {
+        let symbols = parse_tokens(
+            &create_tokens("${for item in items}${item}${endfor}".to_string(), 0).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            symbols,
+            vec![Symbol::Loop {
+                variable: "item".to_string(),
+                iterable: "items".to_string(),
+                body: vec![Symbol::Replace {
+                    identifier: "item".to_string(),
+                    default: None,
+                    format: None,
+                    filters: vec![],
+                    range: create_range(20, 27)
+                }],
+                open_range: create_range(0, 20),
+                close_range: create_range(27, 36)
+            }]
+        );
+        let invalid =
+            parse_tokens(&create_tokens("${for item items}${endfor}".to_string(), 0).unwrap());
+        assert!(matches!(invalid, Err(ParseError::InvalidLoop(_))));
+    }
+
+    #[test]
+    fn test_parsing_filters() {
+        let symbols =
+            parse_tokens(&create_tokens("${var1|trim|upper}".to_string(), 0).unwrap()).unwrap();
+        assert_eq!(
+            symbols,
+            vec![Symbol::Replace {
+                identifier: "var1".to_string(),
+                default: None,
+                format: None,
+                filters: vec!["trim".to_string(), "upper".to_string()],
+                range: create_range(0, 18)
+            }]
+        );
+        let invalid = parse_tokens(&create_tokens("${var1|trim upper}".to_string(), 0).unwrap());
+        assert!(matches!(invalid, Err(ParseError::InvalidFilter(_))));
+    }
+
+    #[test]
+    fn test_parsing_dotted_identifiers() {
+        let symbols = parse_tokens(
+            &create_tokens("${config.db.host|upper}${...config.file}".to_string(), 0).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            symbols,
+            vec![
                 Symbol::Replace {
-                    identifier: "var2".to_string()
+                    identifier: "config.db.host".to_string(),
+                    default: None,
+                    format: None,
+                    filters: vec!["upper".to_string()],
+                    range: create_range(0, 23)
+                },
+                Symbol::Spread {
+                    identifier: "config.file".to_string(),
+                    lines: None,
+                    range: create_range(23, 40)
                 }
             ]
         );
     }
 
     #[test]
-    fn test_parsing_replace_err() {
+    fn test_symbol_table_dotted_lookup() {
+        let config = serde_json::from_str::<serde_json::Value>(
+            r#"{"db": {"host": "localhost", "ports": [80, 443]}}"#,
+        )
+        .unwrap();
+        let symbols = SymbolTable::from_values([("config".to_string(), Value::from(config))]);
+        assert_eq!(
+            symbols.get_variable("config.db.host"),
+            Some("localhost".to_string())
+        );
+        assert_eq!(
+            symbols.get_variable("config.db.ports.0"),
+            Some("80".to_string())
+        );
+        assert_eq!(
+            symbols.get_variable("config.db.ports"),
+            Some("[\"80\",\"443\"]".to_string())
+        );
+        assert_eq!(symbols.get_variable("config.db.user"), None);
+    }
+
+    #[test]
+    fn test_variable_uses() {
         let symbols = parse_tokens(
+            &create_tokens(
+                "${var1} ${for i in items}${i}${...file}${endfor}".to_string(),
+                0,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let uses = variable_uses(&symbols)
+            .into_iter()
+            .map(|each| (each.identifier, each.kind, each.range))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            uses,
+            vec![
+                (
+                    "var1".to_string(),
+                    VariableKind::Replace,
+                    create_range(0, 7)
+                ),
+                ("items".to_string(), VariableKind::Loop, create_range(8, 25)),
+                (
+                    "file".to_string(),
+                    VariableKind::Spread,
+                    create_range(29, 39)
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parsing_replace_err() {
+        let errors = check_tokens(
             &create_tokens("Hello ${var1}! ${var2}".to_string(), 0).unwrap(),
             &SymbolTable::new::<&str>(&[]),
         );
@@ -371,20 +2093,103 @@ mod tests {
             &Position { line: 0, column: 6 },
             &Position {
                 line: 0,
-                column: 12,
+                column: 13,
             },
         )
             .into();
-        if let Err(ParseError::VariableNotFound(r)) = symbols {
-            assert_eq!(r, err_pos)
+        if let [ParseError::VariableNotFound(r), ..] = errors.as_slice() {
+            assert_eq!(errors.len(), 2);
+            assert_eq!(*r, err_pos)
         } else {
             panic!("Expected an error");
         }
     }
 
+    #[test]
+    fn test_check_reports_all_errors() {
+        let tokens = tokenize_text(
+            "${var1} ${if var2}\n${for x in}${x}${endfor} ${var3|}\n${endif} ${endif} ${var4}",
+        )
+        .unwrap();
+        let errors = check_tokens(&tokens, &SymbolTable::new(&[("var2", "2")]));
+        assert!(matches!(
+            errors.as_slice(),
+            [
+                ParseError::VariableNotFound(_),
+                ParseError::InvalidLoop(_),
+                ParseError::InvalidFilter(_),
+                ParseError::UnexpectedBlockEnd(_),
+                ParseError::VariableNotFound(_)
+            ]
+        ));
+        let tokens = tokenize_text("${var1} ${if var2}${var1}${endif}").unwrap();
+        let symbols = SymbolTable::new(&[("var1", "1"), ("var2", "2")]);
+        assert!(check_tokens(&tokens, &symbols).is_empty());
+    }
+
+    #[test]
+    fn test_parsing_with_errors() {
+        let tokens = tokenize_text("${var1|} a ${endfor} ${var2}").unwrap();
+        let (symbols, errors) = parse_tokens_with_errors(&tokens);
+        assert!(matches!(
+            errors.as_slice(),
+            [
+                ParseError::InvalidFilter(_),
+                ParseError::UnexpectedBlockEnd(_)
+            ]
+        ));
+        assert!(matches!(
+            symbols.as_slice(),
+            [Symbol::Word { .. }, Symbol::Replace { .. }]
+        ));
+        let (tokens, errors) = tokenize_reader_with_errors(&b"a\n\xff\nc"[..]);
+        assert_eq!(tokens.len(), 2);
+        assert!(matches!(
+            errors.as_slice(),
+            [ParseError::FailedToReadLine(1)]
+        ));
+    }
+
+    #[test]
+    fn test_parsing_long_template() {
+        // a symbol per token used to be a stack frame
+        let template = "${var1} word\n".repeat(50_000);
+        let tokens = tokenize_text(&template).unwrap();
+        let symbols = parse_tokens(&tokens).unwrap();
+        assert_eq!(symbols.len(), 150_000);
+    }
+
+    #[test]
+    fn test_parsing_deep_nesting() {
+        // deep enough to overflow the stack when blocks could be nested without a limit
+        let template = "${if c}".repeat(2_000) + "x" + &"${endif}".repeat(2_000);
+        let tokens = tokenize_text(&template).unwrap();
+        let (symbols, errors) = parse_tokens_with_errors(&tokens);
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(
+            errors,
+            vec![ParseError::NestingTooDeep(
+                (
+                    &Position {
+                        line: 0,
+                        column: 256 * 7
+                    },
+                    &Position {
+                        line: 0,
+                        column: 256 * 7 + 7
+                    }
+                )
+                    .into()
+            )]
+        );
+        // the deepest blocks allowed are kept
+        let template = "${if c}".repeat(256) + "x" + &"${endif}".repeat(256);
+        assert!(parse_tokens(&tokenize_text(&template).unwrap()).is_ok());
+    }
+
     #[test]
     fn test_parsing_spread_err() {
-        let symbols = parse_tokens(
+        let errors = check_tokens(
             &create_tokens("Hello ${...var1}! ${var2}".to_string(), 0).unwrap(),
             &SymbolTable::new::<&str>(&[]),
         );
@@ -392,12 +2197,13 @@ mod tests {
             &Position { line: 0, column: 6 },
             &Position {
                 line: 0,
-                column: 15,
+                column: 16,
             },
         )
             .into();
-        if let Err(ParseError::FileNotFound(r)) = symbols {
-            assert_eq!(r, err_pos);
+        if let [ParseError::FileNotFound(r), ..] = errors.as_slice() {
+            assert_eq!(errors.len(), 2);
+            assert_eq!(*r, err_pos);
         } else {
             panic!("Expected an error");
         }
@@ -405,30 +2211,72 @@ mod tests {
 
     #[test]
     fn test_parsing_spread() {
-        let symbols = parse_tokens(
-            &create_tokens("Hello ${...var1}! ${...var2}".to_string(), 0).unwrap(),
-            &SymbolTable::new(&[("var1", "Path1"), ("var2", "Path2")]),
-        )
-        .unwrap();
+        let symbols =
+            parse_tokens(&create_tokens("Hello ${...var1}! ${...var2}".to_string(), 0).unwrap())
+                .unwrap();
         assert_eq!(
             symbols,
             vec![
                 Symbol::Word {
-                    text: "Hello".to_string()
+                    text: "Hello".to_string(),
+                    range: create_range(0, 5)
                 },
                 Symbol::Spread {
-                    identifier: "var1".to_string()
+                    identifier: "var1".to_string(),
+                    lines: None,
+                    range: create_range(6, 16)
                 },
                 Symbol::Word {
-                    text: "!".to_string()
+                    text: "!".to_string(),
+                    range: create_range(16, 17)
                 },
                 Symbol::Spread {
-                    identifier: "var2".to_string()
+                    identifier: "var2".to_string(),
+                    lines: None,
+                    range: create_range(18, 28)
                 }
             ]
         );
     }
 
+    #[test]
+    fn test_parsing_spread_lines() {
+        let tokens = tokenize_text("${...var1:3-10} ${...var1:2-}").unwrap();
+        let symbols = parse_tokens(&tokens).unwrap();
+        assert!(matches!(
+            symbols.as_slice(),
+            [
+                Symbol::Spread {
+                    lines: Some(LineRange {
+                        start: 3,
+                        end: Some(10)
+                    }),
+                    ..
+                },
+                Symbol::Spread {
+                    lines: Some(LineRange {
+                        start: 2,
+                        end: None
+                    }),
+                    ..
+                }
+            ]
+        ));
+        for template in [
+            "${...var1:3}",
+            "${...var1:0-2}",
+            "${...var1:5-3}",
+            "${...var1:a-}",
+        ] {
+            let tokens = tokenize_text(template).unwrap();
+            assert!(
+                matches!(parse_tokens(&tokens), Err(ParseError::InvalidLineRange(_))),
+                "{}",
+                template
+            );
+        }
+    }
+
     fn create_word(t: &str, start: usize) -> crate::Token {
         let text = t.to_string();
         let start_pos = Position {
@@ -445,6 +2293,20 @@ mod tests {
         }
     }
 
+    fn create_range(start: usize, end: usize) -> Range {
+        (
+            &Position {
+                line: 0,
+                column: start,
+            },
+            &Position {
+                line: 0,
+                column: end,
+            },
+        )
+            .into()
+    }
+
     fn create_punctuation(t: &str, start: usize) -> crate::Token {
         let chars = t.chars().take(1).collect::<Vec<char>>();
         let value = chars[0];
@@ -454,4 +2316,44 @@ mod tests {
         };
         crate::Token::Punctuation { value, pos }
     }
+
+    #[test]
+    fn test_serialize_tokens() {
+        let tokens = tokenize_text("a ${b}").unwrap();
+        assert_eq!(
+            serde_json::to_value(&tokens[..2]).unwrap(),
+            json!([
+                {"Word": {"text": "a", "range": {
+                    "start_pos": {"line": 0, "column": 0},
+                    "end_pos": {"line": 0, "column": 1}
+                }}},
+                {"Punctuation": {"value": "$", "pos": {"line": 0, "column": 2}}}
+            ])
+        );
+    }
+
+    #[test]
+    fn test_serialize_symbols() {
+        let tokens = tokenize_text("${if a == 1}${...f:2-}${endif}").unwrap();
+        let symbols = parse_tokens(&tokens).unwrap();
+        let range = |start, end| {
+            json!({
+                "start_pos": {"line": 0, "column": start},
+                "end_pos": {"line": 0, "column": end}
+            })
+        };
+        assert_eq!(
+            serde_json::to_value(&symbols).unwrap(),
+            json!([{"Conditional": {
+                "condition": {"Equals": ["a", "1"]},
+                "body": [{"Spread": {
+                    "identifier": "f",
+                    "lines": {"start": 2, "end": null},
+                    "range": range(12, 22)
+                }}],
+                "open_range": range(0, 12),
+                "close_range": range(22, 30)
+            }}])
+        );
+    }
 }
@@ -0,0 +1,181 @@
+use std::fs;
+use std::path;
+
+/// Whether `path` (`/` separated) matches `pattern`. `*` matches any part of a file name, `**`
+/// any number of directories and `?` a single character
+pub fn glob_matches(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+    matches_from(&pattern, &path)
+}
+
+/// Whether `pattern` has any wildcards
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// Paths of the files matching `pattern`, sorted. Only the directory before the first wildcard is
+/// walked, as deep as the rest of the pattern goes (all the way down with `**`)
+pub fn glob_files(pattern: &str) -> Vec<String> {
+    let segments: Vec<&str> = pattern.split('/').collect();
+    let literal = segments
+        .iter()
+        .take_while(|segment| !is_glob(segment))
+        .count();
+    let base = match segments[..literal].join("/") {
+        base if base.is_empty() && pattern.starts_with('/') => "/".to_string(),
+        base => base,
+    };
+    let rest = segments[literal..].join("/");
+    let depth = if segments[literal..]
+        .iter()
+        .any(|segment| segment.contains("**"))
+    {
+        None
+    } else {
+        Some(segments.len() - literal)
+    };
+    let base_dir = if base.is_empty() { "." } else { base.as_str() };
+    let mut files: Vec<String> = relative_files(path::Path::new(base_dir), depth)
+        .into_iter()
+        .filter(|relative_path| glob_matches(&rest, relative_path))
+        .map(|relative_path| match base.as_str() {
+            "" => relative_path,
+            base if base.ends_with('/') => format!("{}{}", base, relative_path),
+            base => format!("{}/{}", base, relative_path),
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+// `/` separated paths of the files under `dir`, `depth` levels deep at most (`None` for no limit).
+// Symbolic links are followed, but not to directories when there's no limit (they could make a
+// cycle). Directories that can't be read and broken links are skipped
+fn relative_files(dir: &path::Path, depth: Option<usize>) -> Vec<String> {
+    let mut files = vec![];
+    let mut pending = vec![(String::new(), 1)];
+    while let Some((relative_dir, level)) = pending.pop() {
+        let entries = match fs::read_dir(dir.join(&relative_dir)) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let relative_path = if relative_dir.is_empty() {
+                name
+            } else {
+                format!("{}/{}", relative_dir, name)
+            };
+            let is_link = entry
+                .file_type()
+                .is_ok_and(|file_type| file_type.is_symlink());
+            match fs::metadata(entry.path()) {
+                Ok(metadata) if metadata.is_dir() => {
+                    if depth.map_or(!is_link, |depth| level < depth) {
+                        pending.push((relative_path, level + 1))
+                    }
+                }
+                Ok(_) => files.push(relative_path),
+                Err(_) => {}
+            }
+        }
+    }
+    files
+}
+
+fn matches_from(pattern: &[char], path: &[char]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        ['*', '*', '/', rest @ ..] => {
+            // zero or more whole directories
+            matches_from(rest, path)
+                || (0..path.len())
+                    .filter(|&i| path[i] == '/')
+                    .any(|i| matches_from(rest, &path[i + 1..]))
+        }
+        ['*', '*', rest @ ..] => (0..=path.len()).any(|i| matches_from(rest, &path[i..])),
+        ['*', rest @ ..] => (0..=path.len())
+            .take_while(|&i| i == 0 || path[i - 1] != '/')
+            .any(|i| matches_from(rest, &path[i..])),
+        ['?', rest @ ..] => {
+            matches!(path.first(), Some(c) if *c != '/') && matches_from(rest, &path[1..])
+        }
+        [c, rest @ ..] => path.first() == Some(c) && matches_from(rest, &path[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{glob_files, glob_matches};
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("*.tmpl", "a.tmpl"));
+        assert!(!glob_matches("*.tmpl", "dir/a.tmpl"));
+        assert!(!glob_matches("*.tmpl", "a.tmpl.bak"));
+        assert!(glob_matches("**/*.tmpl", "a.tmpl"));
+        assert!(glob_matches("**/*.tmpl", "dir/sub/a.tmpl"));
+        assert!(glob_matches("dir/**", "dir/sub/a.txt"));
+        assert!(glob_matches("file?.txt", "file1.txt"));
+        assert!(!glob_matches("file?.txt", "file.txt"));
+        assert!(glob_matches("*", ""));
+    }
+
+    #[test]
+    fn test_glob_files() {
+        assert_eq!(
+            glob_files("./test_corpus/vars.*"),
+            vec![
+                "./test_corpus/vars.json",
+                "./test_corpus/vars.toml",
+                "./test_corpus/vars.yaml"
+            ]
+        );
+        assert_eq!(
+            glob_files("test_corpus/c*.txt"),
+            vec!["test_corpus/cycle.txt"]
+        );
+        assert!(glob_files("./test_corpus/*.missing").is_empty());
+    }
+
+    #[test]
+    fn test_glob_files_depth() {
+        let dir = std::env::temp_dir().join("ptemplator_test_glob_depth");
+        std::fs::create_dir_all(dir.join("a/b")).unwrap();
+        std::fs::write(dir.join("top.txt"), "").unwrap();
+        std::fs::write(dir.join("a/mid.txt"), "").unwrap();
+        std::fs::write(dir.join("a/b/deep.txt"), "").unwrap();
+        #[cfg(unix)]
+        {
+            let _ = std::fs::remove_file(dir.join("link"));
+            std::os::unix::fs::symlink(dir.join("a"), dir.join("link")).unwrap();
+        }
+        let base = dir.display().to_string();
+        let relative = |files: Vec<String>| -> Vec<String> {
+            files
+                .into_iter()
+                .map(|file| file[base.len() + 1..].to_string())
+                .collect()
+        };
+        assert_eq!(
+            relative(glob_files(&format!("{}/*.txt", base))),
+            vec!["top.txt"]
+        );
+        // a linked directory is a directory, not a file
+        assert_eq!(
+            relative(glob_files(&format!("{}/*", base))),
+            vec!["top.txt"]
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            relative(glob_files(&format!("{}/*/*.txt", base))),
+            vec!["a/mid.txt", "link/mid.txt"]
+        );
+        assert_eq!(
+            relative(glob_files(&format!("{}/**/*.txt", base))),
+            vec!["a/b/deep.txt", "a/mid.txt", "top.txt"]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,69 @@
+use std::io;
+use std::process::Command;
+
+use crate::back::ContentProvider;
+
+/// Content served over `http://` and `https://`, fetched with `curl` (which has to be on the
+/// `PATH`). Redirects are followed and error statuses fail the spread
+pub struct HttpProvider;
+
+impl ContentProvider for HttpProvider {
+    fn handles(&self, location: &str) -> bool {
+        location.starts_with("http://") || location.starts_with("https://")
+    }
+
+    fn read(&self, location: &str) -> io::Result<String> {
+        let output = Command::new("curl")
+            .args(["--fail", "--silent", "--show-error", "--location", "--"])
+            .arg(location)
+            .output()?;
+        if !output.status.success() {
+            let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            return Err(io::Error::other(message));
+        }
+        String::from_utf8(output.stdout)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::HttpProvider;
+    use crate::back::ContentProvider;
+    use crate::front::SymbolTable;
+
+    // Serve `body` to a single request
+    fn serve(body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        });
+        format!("http://{}/snippet.txt", address)
+    }
+
+    #[test]
+    fn test_http_provider() {
+        assert!(HttpProvider.handles("https://example.com/a.txt"));
+        assert!(!HttpProvider.handles("./test_corpus/snippet.txt"));
+        let url = serve("Hello ${name}\n");
+        let symbols = SymbolTable::new(&[("snippet", url.as_str()), ("name", "world")]);
+        assert_eq!(
+            crate::render("${...snippet}!", &symbols).unwrap(),
+            "Hello world!"
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use std::io::BufRead;
+
+use crate::front::append_text;
+
+mod back;
+mod builtins;
+mod diagnostic;
+mod filters;
+mod front;
+mod glob;
+#[cfg(feature = "http")]
+mod http;
+mod newline;
+mod resolver;
+mod roundtrip;
+mod session;
+mod stream;
+mod value;
+mod vars;
+
+pub use back::{
+    to_output_tokens, to_output_tokens_with_errors, to_output_tokens_with_options, CachingProvider,
+    ContentProvider, FileSystemProvider, TextGenError, TextGenOptions, MAX_EXPANSION_DEPTH,
+};
+pub use builtins::BUILTIN_VARIABLES;
+pub use diagnostic::{Diagnostic, RenderError};
+pub use filters::{Filter, Filters};
+pub use front::{
+    check_tokens, check_tokens_with_delimiters, create_tokens, parse_tokens,
+    parse_tokens_with_delimiters, parse_tokens_with_errors, reconstruct_text, tokenize_reader,
+    tokenize_reader_with_errors, tokenize_text, variable_uses, Delimiters, Identifier, LineRange,
+    ParseError, Position, Range, Symbol, SymbolTable, Token, Tokens, VariableKind, VariableUse,
+};
+pub use glob::{glob_files, glob_matches, is_glob};
+#[cfg(feature = "http")]
+pub use http::HttpProvider;
+pub use newline::LineEnding;
+pub use resolver::{ChainResolver, EnvResolver, Resolver};
+pub use roundtrip::{verify_roundtrip, RoundtripMismatch};
+pub use session::Session;
+pub use stream::{render_stream, StreamError};
+pub use value::Value;
+pub use vars::{load_vars_file, save_vars_file, VarsFileError};
+
+/// Render `template` by running it through every stage of the pipeline
+/// (tokenize -> parse -> substitute -> generate text)
+pub fn render(template: &str, symbols: &SymbolTable) -> Result<String, RenderError> {
+    render_with_options(template, symbols, &TextGenOptions::default())
+}
+
+/// Same as `render` but with the given options (custom filters, lenient rendering)
+pub fn render_with_options(
+    template: &str,
+    symbols: &SymbolTable,
+    options: &TextGenOptions,
+) -> Result<String, RenderError> {
+    let tokens = tokenize_text(template)?;
+    let (parsed, parse_errors) = parse_tokens_with_delimiters(&tokens, &options.delimiters);
+    if let Some(err) = parse_errors.into_iter().next() {
+        return Err(err.into());
+    }
+    let output_tokens = to_output_tokens_with_options(&parsed, symbols, options)?;
+    let ending = LineEnding::detect(template.as_bytes()).unwrap_or_default();
+    Ok(output_text(&output_tokens, ending, options))
+}
+
+/// Same as `render_with_options` but every stage goes on after an error so that all the problems
+/// of the template are reported at once
+pub fn render_with_diagnostics<R: BufRead>(
+    mut template: R,
+    symbols: &SymbolTable,
+    options: &TextGenOptions,
+) -> Result<String, Vec<Diagnostic>> {
+    // the line ending of the first line is needed before the text is generated
+    let mut source = vec![];
+    if template.read_to_end(&mut source).is_err() {
+        return Err(vec![ParseError::FailedToReadLine(0).into()]);
+    }
+    let ending = LineEnding::detect(&source).unwrap_or_default();
+    let (tokens, token_errors) = tokenize_reader_with_errors(source.as_slice());
+    render_tokens(&tokens, token_errors, ending, symbols, options)
+}
+
+// Parse and generate the tokens of a template, `token_errors` are the problems found while
+// tokenizing it
+pub(crate) fn render_tokens(
+    tokens: &[Token],
+    token_errors: Vec<ParseError>,
+    ending: LineEnding,
+    symbols: &SymbolTable,
+    options: &TextGenOptions,
+) -> Result<String, Vec<Diagnostic>> {
+    let (parsed, parse_errors) = parse_tokens_with_delimiters(tokens, &options.delimiters);
+    let (output_tokens, text_gen_errors) = to_output_tokens_with_errors(&parsed, symbols, options);
+    let diagnostics: Vec<Diagnostic> = token_errors
+        .into_iter()
+        .chain(parse_errors)
+        .map(Diagnostic::from)
+        .chain(text_gen_errors.into_iter().map(Diagnostic::from))
+        .collect();
+    if diagnostics.is_empty() {
+        Ok(output_text(&output_tokens, ending, options))
+    } else {
+        Err(diagnostics)
+    }
+}
+
+// Text of the output of a template whose lines end with `ending`. The line breaks of the template
+// are kept and the lines of generated text (multi-line values) end with `ending`, unless the
+// options give a line ending for every line
+pub(crate) fn output_text(
+    tokens: &[Token],
+    ending: LineEnding,
+    options: &TextGenOptions,
+) -> String {
+    let mut text = String::new();
+    let mut current = Position { line: 0, column: 0 };
+    append_text(tokens, &mut current, ending.as_str(), &mut text);
+    match options.line_ending {
+        Some(ending) => ending.convert(&text),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        render, render_with_diagnostics, Diagnostic, LineEnding, ParseError, RenderError,
+        SymbolTable, TextGenError, TextGenOptions,
+    };
+
+    #[test]
+    fn test_render_multiline() {
+        let symbols = SymbolTable::new(&[("name", "world"), ("greeting", "Good morning")]);
+        let text = render("${greeting}\n  Hello ${name}!", &symbols).unwrap();
+        assert_eq!(text, "Good morning\n  Hello world!");
+    }
+
+    #[test]
+    fn test_render_errors() {
+        let symbols = SymbolTable::new::<&str>(&[]);
+        assert!(matches!(
+            render("Hello ${if}${endif}", &symbols),
+            Err(RenderError::Parse { .. })
+        ));
+        assert!(matches!(
+            render("Hello ${name}!", &symbols),
+            Err(RenderError::TextGen { .. })
+        ));
+    }
+
+    #[test]
+    fn test_render_with_diagnostics() {
+        let symbols = SymbolTable::new(&[("items", "a,b"), ("file", "./does_not_exist.txt")]);
+        let template = "${if}\n${for i in items}${i}${var1}${endfor}\n${...file} ${var2}";
+        let diagnostics =
+            render_with_diagnostics(template.as_bytes(), &symbols, &TextGenOptions::default());
+        assert!(matches!(
+            diagnostics.unwrap_err().as_slice(),
+            [
+                Diagnostic::Parse {
+                    error: ParseError::InvalidCondition(_),
+                    ..
+                },
+                Diagnostic::Parse {
+                    error: ParseError::UnclosedBlock(_),
+                    ..
+                },
+                Diagnostic::TextGen {
+                    error: TextGenError::VariableNotFound(_),
+                    ..
+                },
+                Diagnostic::TextGen {
+                    error: TextGenError::FailedToReadFile(_),
+                    ..
+                },
+                Diagnostic::TextGen {
+                    error: TextGenError::VariableNotFound(_),
+                    ..
+                }
+            ]
+        ));
+        let text = render_with_diagnostics("${items}".as_bytes(), &symbols, &Default::default());
+        assert_eq!(text.unwrap(), "a,b");
+    }
+
+    #[test]
+    fn test_render_keeps_newlines() {
+        let symbols = SymbolTable::new(&[("name", "world")]);
+        let template = "Hello\r\n${name}\r\n\r\n";
+        assert_eq!(
+            render(template, &symbols).unwrap(),
+            template.replace("${name}", "world")
+        );
+        let options = TextGenOptions {
+            line_ending: Some(LineEnding::Lf),
+            ..Default::default()
+        };
+        let text = render_with_diagnostics(template.as_bytes(), &symbols, &options);
+        assert_eq!(text.unwrap(), "Hello\nworld\n\n");
+        // mixed line breaks are kept, generated lines end like the first line
+        let symbols = SymbolTable::new(&[("name", "a\nb")]);
+        let text = render("x\r\n${name}\ny\r\n\n\n", &symbols).unwrap();
+        assert_eq!(text, "x\r\na\r\nb\ny\r\n\n\n");
+    }
+}
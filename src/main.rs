@@ -1,121 +1,685 @@
 use std::env;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::num;
 use std::path;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
 
-mod front;
-use front::{create_tokens, parse_tokens, reconstruct_text, Identifier, ParseError, Token};
-
-use crate::front::SymbolTable;
-
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let file_path = path::PathBuf::from(args[1].clone());
-    let symbol_table = symbol_table_from_args(&args[2..]);
-    match tokenize_file(&file_path) {
-        Err(err) => print_error(err, &file_path),
-        Ok(tokens) => {
-            println!("{:?} \n {}", &tokens, reconstruct_text(&tokens));
-            match parse_tokens(&tokens, &symbol_table) {
-                Err(err) => print_error(err, &file_path),
-                Ok(symbols) => {
-                    println!("{:?}", &symbols);
+use ptemplator::{
+    check_tokens_with_delimiters, parse_tokens_with_delimiters, render_stream,
+    render_with_diagnostics, save_vars_file, tokenize_reader, tokenize_reader_with_errors,
+    variable_uses, CachingProvider, ContentProvider, Delimiters, Diagnostic, LineEnding,
+    ParseError, StreamError, SymbolTable, TextGenOptions,
+};
+
+mod cli;
+mod completions;
+mod dir;
+mod prompt;
+mod report;
+mod watch;
+use cli::{Command, Output, Prompt, VarArgs};
+use report::Reporter;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match cli::parse_args(&args) {
+        Err(err) => Reporter::new(cli::error_format(&args)).argument_failure(&err),
+        Ok(Command::Help) => {
+            println!("{}", cli::USAGE);
+            ExitCode::SUCCESS
+        }
+        Ok(Command::Completions(shell)) => {
+            print!("{}", completions::script(shell));
+            ExitCode::SUCCESS
+        }
+        Ok(Command::Render {
+            templates,
+            vars,
+            output,
+            lenient,
+            reindent,
+            expand,
+            spread_separator,
+            cache,
+            newline,
+            stream,
+            watch,
+            prompt,
+            jobs,
+            delimiters,
+            error_format,
+        }) => {
+            let mut options = text_gen_options(
+                lenient,
+                reindent,
+                expand,
+                cache,
+                spread_separator,
+                newline,
+                delimiters,
+            );
+            // a watched template reads the spread files again when they change
+            if cache && !watch {
+                share_spreads(&mut options);
+            }
+            let reporter = Reporter::new(error_format);
+            let render = || {
+                if stream {
+                    // --stream takes a single template
+                    render_streaming(&templates[0], &vars, &output, &options, &reporter)
+                } else {
+                    let jobs = jobs_or_cores(jobs);
+                    render(
+                        &templates, &vars, &output, &prompt, jobs, &options, &reporter,
+                    )
                 }
+            };
+            if watch {
+                // --watch takes a single template
+                watch_template(&templates[0], &vars, &options.delimiters, &reporter, render)
+            } else {
+                render()
             }
         }
+        Ok(Command::Check {
+            template,
+            vars,
+            delimiters,
+            error_format,
+        }) => check(&template, &vars, &delimiters, &Reporter::new(error_format)),
+        Ok(Command::Vars {
+            template,
+            delimiters,
+            error_format,
+        }) => list_vars(&template, &delimiters, &Reporter::new(error_format)),
+        Ok(Command::Dump {
+            template,
+            delimiters,
+            error_format,
+        }) => dump(&template, &delimiters, &Reporter::new(error_format)),
+        Ok(Command::RenderDir {
+            src_dir,
+            out_dir,
+            vars,
+            pattern,
+            lenient,
+            reindent,
+            expand,
+            spread_separator,
+            cache,
+            newline,
+            jobs,
+            delimiters,
+            error_format,
+        }) => {
+            let mut options = text_gen_options(
+                lenient,
+                reindent,
+                expand,
+                cache,
+                spread_separator,
+                newline,
+                delimiters,
+            );
+            if cache {
+                share_spreads(&mut options);
+            }
+            let reporter = Reporter::new(error_format);
+            render_dir(
+                &src_dir,
+                &out_dir,
+                &vars,
+                pattern.as_deref(),
+                jobs_or_cores(jobs),
+                &options,
+                &reporter,
+            )
+        }
+    }
+}
+
+fn text_gen_options(
+    lenient: bool,
+    reindent: bool,
+    expand_values: bool,
+    cache_spreads: bool,
+    spread_separator: Option<String>,
+    line_ending: Option<LineEnding>,
+    delimiters: Delimiters,
+) -> TextGenOptions {
+    let default = TextGenOptions::default();
+    TextGenOptions {
+        lenient,
+        reindent,
+        expand_values,
+        cache_spreads,
+        spread_separator: spread_separator.unwrap_or(default.spread_separator),
+        line_ending,
+        delimiters,
+        ..default
     }
 }
 
-fn symbol_table_from_args(args: &[String]) -> SymbolTable {
-    SymbolTable::new(
-        &args
-            .chunks(2)
-            .map(|chunk| match chunk {
-                [key, value] => (parse_identifier(key), parse_variable(value)),
-                _ => {
-                    panic!("Invalid symbol {:?}", chunk)
+// The files spread into many templates are only read once
+fn share_spreads(options: &mut TextGenOptions) {
+    options.content_providers = std::mem::take(&mut options.content_providers)
+        .into_iter()
+        .map(|provider| Box::new(CachingProvider::new(provider)) as Box<dyn ContentProvider>)
+        .collect();
+}
+
+fn jobs_or_cores(jobs: Option<usize>) -> usize {
+    jobs.unwrap_or_else(|| thread::available_parallelism().map_or(1, num::NonZeroUsize::get))
+}
+
+// Runs `job` with every index below `count`, `jobs` of them at the same time. The exit code is the
+// one of the last job (in index order) that failed
+fn run_jobs(count: usize, jobs: usize, job: impl Fn(usize) -> Option<ExitCode> + Sync) -> ExitCode {
+    let next = AtomicUsize::new(0);
+    let exit_codes = Mutex::new(vec![None; count]);
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, count.max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                if i >= count {
+                    break;
                 }
-            })
-            .collect::<Vec<(String, String)>>(),
+                let exit_code = job(i);
+                exit_codes.lock().unwrap()[i] = exit_code;
+            });
+        }
+    });
+    exit_codes
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .flatten()
+        .last()
+        .unwrap_or(ExitCode::SUCCESS)
+}
+
+// Every problem of the templates is reported before giving up, `jobs` of them are rendered at the
+// same time. The outputs written to stdout are in the order of the templates (once they are all
+// rendered), the exit code is the one of the last template that failed
+fn render(
+    templates: &[path::PathBuf],
+    vars: &VarArgs,
+    output: &Output,
+    prompt: &Prompt,
+    jobs: usize,
+    options: &TextGenOptions,
+    reporter: &Reporter,
+) -> ExitCode {
+    let symbol_table = match vars.symbol_table() {
+        Ok(symbol_table) => symbol_table,
+        Err(err) => return reporter.usage_failure(&err.to_string()),
+    };
+    if let Prompt::Missing { save_vars } = prompt {
+        if !io::stdin().is_terminal() {
+            let message = "--interactive needs a terminal to ask for the variables";
+            return reporter.usage_failure(message);
+        }
+        // --interactive takes a single template
+        let template = &templates[0];
+        let source = match read_template(template) {
+            Ok(source) => source,
+            Err(err) => return reporter.diagnostics(vec![err.into()], template, ""),
+        };
+        let symbol_table = match ask_missing(&source, symbol_table, save_vars, options) {
+            Ok(symbol_table) => symbol_table,
+            Err(err) => return reporter.io_failure(None, &err),
+        };
+        return match render_with_diagnostics(source.as_bytes(), &symbol_table, options) {
+            Err(diagnostics) => reporter.diagnostics(diagnostics, template, &source),
+            Ok(text) => {
+                write_rendered(&text, output, template, reporter).unwrap_or(ExitCode::SUCCESS)
+            }
+        };
+    }
+    let texts = Mutex::new(vec![None; templates.len()]);
+    let exit_code = run_jobs(templates.len(), jobs, |i| {
+        let template = &templates[i];
+        let text = match render_file(template, &symbol_table, options, reporter) {
+            Ok(text) => text,
+            Err(exit_code) => return Some(exit_code),
+        };
+        match output {
+            Output::Stdout => {
+                texts.lock().unwrap()[i] = Some(text);
+                None
+            }
+            _ => write_rendered(&text, output, template, reporter),
+        }
+    });
+    let mut exit_code = exit_code;
+    let texts = texts.into_inner().unwrap();
+    for (text, template) in texts.into_iter().zip(templates) {
+        if let Some(text) = text {
+            exit_code =
+                write_rendered(&text, &Output::Stdout, template, reporter).unwrap_or(exit_code);
+        }
+    }
+    exit_code
+}
+
+// Exit code of the failure (once it's reported), if writing the output of the template fails
+fn write_rendered(
+    text: &str,
+    output: &Output,
+    template: &path::Path,
+    reporter: &Reporter,
+) -> Option<ExitCode> {
+    write_output(text, output, template).err().map(|err| {
+        let message = format!("Failed to write output ({})", err);
+        reporter.io_failure(Some(template), &message)
+    })
+}
+
+// Renders until interrupted, errors are reported and the next change is waited for
+fn watch_template(
+    template: &path::Path,
+    vars: &VarArgs,
+    delimiters: &Delimiters,
+    reporter: &Reporter,
+    render: impl Fn() -> ExitCode,
+) -> ExitCode {
+    if template == path::Path::new(STDIN_PATH) {
+        return reporter.usage_failure("stdin can't be watched");
+    }
+    loop {
+        render();
+        // a changed vars file can make spreads point to other files
+        let symbol_table = vars
+            .symbol_table()
+            .unwrap_or_else(|_| SymbolTable::new::<&str>(&[]));
+        let files = watch::watched_files(template, vars.vars_files(), &symbol_table, delimiters);
+        reporter.note(&format!("Watching {} files for changes", files.len()));
+        watch::wait_for_change(&files);
+    }
+}
+
+// Answers are read from stdin, which has to be a terminal (so the template can't come from stdin),
+// writing them to `save_vars`
+fn ask_missing(
+    source: &str,
+    symbol_table: SymbolTable,
+    save_vars: &Option<path::PathBuf>,
+    options: &TextGenOptions,
+) -> Result<SymbolTable, String> {
+    let (symbol_table, answers) = prompt::ask_missing(
+        source,
+        symbol_table,
+        options,
+        io::stdin().lock(),
+        io::stderr(),
     )
+    .map_err(|err| format!("Failed to read the answers ({})", err))?;
+    if let Some(file_path) = save_vars {
+        save_vars_file(file_path, &answers)
+            .map_err(|err| format!("{}: {}", file_path.display(), err))?;
+    }
+    Ok(symbol_table)
 }
 
-fn parse_identifier(value: &str) -> Identifier {
-    if !value.starts_with("--") {
-        panic!(
-            "Invalid variable name {}: use --<VarName> <Var value>",
-            value
+// The template is never fully in memory, so errors are reported without the offending lines
+fn render_streaming(
+    template: &path::Path,
+    vars: &VarArgs,
+    output: &Output,
+    options: &TextGenOptions,
+    reporter: &Reporter,
+) -> ExitCode {
+    let symbol_table = match vars.symbol_table() {
+        Ok(symbol_table) => symbol_table,
+        Err(err) => return reporter.usage_failure(&err.to_string()),
+    };
+    let reader: Box<dyn BufRead> = if template == path::Path::new(STDIN_PATH) {
+        Box::new(io::stdin().lock())
+    } else {
+        match fs::File::open(template) {
+            Ok(file) => Box::new(io::BufReader::new(file)),
+            Err(_) => {
+                let diagnostics = vec![ParseError::FailedToOpenFile.into()];
+                return reporter.diagnostics(diagnostics, template, "");
+            }
+        }
+    };
+    let mut writer: Box<dyn Write> = match output {
+        Output::File(path) => match fs::File::create(path) {
+            Ok(file) => Box::new(io::BufWriter::new(file)),
+            Err(err) => {
+                let message = format!("Failed to write output ({})", err);
+                return reporter.io_failure(Some(template), &message);
+            }
+        },
+        _ => Box::new(io::BufWriter::new(io::stdout().lock())),
+    };
+    let result = render_stream(reader, &mut writer, &symbol_table, options);
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(StreamError::Template(diagnostics)) => reporter.diagnostics(diagnostics, template, ""),
+        Err(err) => reporter.io_failure(Some(template), &err.to_string()),
+    }
+}
+
+// Files that are not templates are copied as they are. Every file is tried even when some fail,
+// `jobs` of them at the same time. The exit code is the one of the last file (in path order) that
+// failed
+fn render_dir(
+    src_dir: &path::Path,
+    out_dir: &path::Path,
+    vars: &VarArgs,
+    pattern: Option<&str>,
+    jobs: usize,
+    options: &TextGenOptions,
+    reporter: &Reporter,
+) -> ExitCode {
+    let symbol_table = match vars.symbol_table() {
+        Ok(symbol_table) => symbol_table,
+        Err(err) => return reporter.usage_failure(&err.to_string()),
+    };
+    let files = match dir::files(src_dir, out_dir) {
+        Ok(files) => files,
+        Err(err) => {
+            let message = format!("Failed to read directory ({})", err);
+            return reporter.io_failure(Some(src_dir), &message);
+        }
+    };
+    run_jobs(files.len(), jobs, |i| {
+        render_dir_file(
+            &src_dir.join(&files[i]),
+            &out_dir.join(&files[i]),
+            dir::is_template(&files[i], pattern),
+            &symbol_table,
+            options,
+            reporter,
+        )
+    })
+}
+
+// Render (or copy, when it's not a template) a file of the directory, the exit code of the failure
+// if it fails
+fn render_dir_file(
+    src_path: &path::Path,
+    out_path: &path::Path,
+    is_template: bool,
+    symbol_table: &SymbolTable,
+    options: &TextGenOptions,
+    reporter: &Reporter,
+) -> Option<ExitCode> {
+    let copied = match out_path.parent().map_or(Ok(()), fs::create_dir_all) {
+        Ok(()) if is_template => {
+            let output = Output::File(out_path.to_path_buf());
+            return match render_file(src_path, symbol_table, options, reporter) {
+                Ok(text) => write_rendered(&text, &output, src_path, reporter),
+                Err(exit_code) => Some(exit_code),
+            };
+        }
+        Ok(()) => fs::copy(src_path, out_path).map(|_| ()),
+        Err(err) => Err(err),
+    };
+    copied.err().map(|err| {
+        let message = format!("Failed to write output ({})", err);
+        reporter.io_failure(Some(src_path), &message)
+    })
+}
+
+// Output of the template, the exit code of the failure (once it's reported) if rendering it fails
+fn render_file(
+    src_path: &path::Path,
+    symbol_table: &SymbolTable,
+    options: &TextGenOptions,
+    reporter: &Reporter,
+) -> Result<String, ExitCode> {
+    let source = match read_template(src_path) {
+        Ok(source) => source,
+        Err(err) => return Err(reporter.diagnostics(vec![err.into()], src_path, "")),
+    };
+    render_with_diagnostics(source.as_bytes(), symbol_table, options)
+        .map_err(|diagnostics| reporter.diagnostics(diagnostics, src_path, &source))
+}
+
+fn check(
+    template: &path::Path,
+    vars: &VarArgs,
+    delimiters: &Delimiters,
+    reporter: &Reporter,
+) -> ExitCode {
+    let symbol_table = match vars.symbol_table() {
+        Ok(symbol_table) => symbol_table,
+        Err(err) => return reporter.usage_failure(&err.to_string()),
+    };
+    let source = match read_template(template) {
+        Ok(source) => source,
+        Err(err) => return reporter.diagnostics(vec![err.into()], template, ""),
+    };
+    let errors = check_template(&source, &symbol_table, delimiters);
+    if errors.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        reporter.diagnostics(
+            errors.into_iter().map(Diagnostic::from).collect(),
+            template,
+            &source,
         )
     }
-    value[2..].to_string()
 }
 
-fn parse_variable(value: &str) -> String {
-    if value.starts_with('"') {
-        if !value.ends_with('"') {
-            panic!("Varible value not terminated {}", value)
+fn list_vars(template: &path::Path, delimiters: &Delimiters, reporter: &Reporter) -> ExitCode {
+    let source = match read_template(template) {
+        Ok(source) => source,
+        Err(err) => return reporter.diagnostics(vec![err.into()], template, ""),
+    };
+    let parsed = tokenize_reader(source.as_bytes()).and_then(|tokens| {
+        let (symbols, errors) = parse_tokens_with_delimiters(&tokens, delimiters);
+        errors.into_iter().next().map_or(Ok(symbols), Err)
+    });
+    match parsed {
+        Err(err) => reporter.diagnostics(vec![err.into()], template, &source),
+        Ok(symbols) => {
+            for variable in variable_uses(&symbols) {
+                println!(
+                    "{:?} {} {}",
+                    variable.range.start_pos, variable.kind, variable.identifier
+                );
+            }
+            ExitCode::SUCCESS
         }
-        value[1..(value.len() - 1)].to_string()
+    }
+}
+
+// What could be parsed is written even when there are errors, they are reported after it
+fn dump(template: &path::Path, delimiters: &Delimiters, reporter: &Reporter) -> ExitCode {
+    let source = match read_template(template) {
+        Ok(source) => source,
+        Err(err) => return reporter.diagnostics(vec![err.into()], template, ""),
+    };
+    let (tokens, mut errors) = tokenize_reader_with_errors(source.as_bytes());
+    let (symbols, parse_errors) = parse_tokens_with_delimiters(&tokens, delimiters);
+    errors.extend(parse_errors);
+    let dump = serde_json::json!({ "tokens": tokens, "symbols": symbols });
+    println!("{}", dump);
+    if errors.is_empty() {
+        ExitCode::SUCCESS
     } else {
-        value.to_string()
+        reporter.diagnostics(
+            errors.into_iter().map(Diagnostic::from).collect(),
+            template,
+            &source,
+        )
     }
 }
 
-fn print_error(err: ParseError, file_path: &path::Path) {
-    eprintln!("{}:{:?}", file_path.to_str().unwrap(), err);
+// Lines that fail to tokenize are reported and left out of the rest of the check
+fn check_template(
+    source: &str,
+    symbol_table: &SymbolTable,
+    delimiters: &Delimiters,
+) -> Vec<ParseError> {
+    let (tokens, mut errors) = tokenize_reader_with_errors(source.as_bytes());
+    errors.extend(check_tokens_with_delimiters(
+        &tokens,
+        symbol_table,
+        delimiters,
+    ));
+    errors
 }
 
-fn tokenize_file(file_path: &path::Path) -> Result<Vec<Token>, ParseError> {
+fn write_output(text: &str, output: &Output, file_path: &path::Path) -> io::Result<()> {
+    match output {
+        Output::Stdout => io::stdout().write_all(text.as_bytes()),
+        Output::File(path) => fs::write(path, text),
+        Output::InPlace { .. } if file_path == path::Path::new(STDIN_PATH) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "stdin can't be edited in place",
+        )),
+        Output::InPlace { backup } => {
+            if *backup {
+                let mut backup_path = file_path.as_os_str().to_owned();
+                backup_path.push(".bak");
+                fs::copy(file_path, backup_path)?;
+            }
+            fs::write(file_path, text)
+        }
+    }
+}
+
+// Template is read from stdin when the path is `-`
+pub const STDIN_PATH: &str = "-";
+
+fn read_template(file_path: &path::Path) -> Result<String, ParseError> {
+    if file_path == path::Path::new(STDIN_PATH) {
+        let mut source = String::new();
+        return match io::stdin().read_to_string(&mut source) {
+            Ok(_) => Ok(source),
+            Err(_) => Err(ParseError::FailedToOpenFile),
+        };
+    }
     if !file_path.is_file() {
         return Err(ParseError::InvalidFilePath);
     }
-    match File::open(file_path) {
-        Ok(file) => {
-            let reader = BufReader::new(file);
-            reader
-                .lines()
-                .enumerate()
-                .try_fold(Vec::new(), |acc, (i, l)| match l {
-                    Ok(t) => Ok(acc.into_iter().chain(create_tokens(t, i)?).collect()),
-                    Err(_) => Err(ParseError::FailedToReadLine(i)),
-                })
-        }
-        Err(_) => Err(ParseError::FailedToOpenFile),
-    }
+    fs::read_to_string(file_path).map_err(|_| ParseError::FailedToOpenFile)
 }
 
 #[cfg(test)]
 mod test {
-    use std::{fs::File, io::BufRead, io::BufReader, path};
+    use std::{fs, path};
 
-    use crate::symbol_table_from_args;
+    use crate::{check_template, render, render_dir, write_output, Output};
 
     #[test]
     fn test_roundtrip_simple_file() {
         let file_path = path::PathBuf::from("./test_corpus/simple.txt");
-        let tokens = crate::tokenize_file(&file_path).unwrap();
-        let expected_text = crate::front::reconstruct_text(&tokens);
-        let actual_text = read_file_as_string(&file_path);
+        let source = crate::read_template(&file_path).unwrap();
+        let tokens = ptemplator::tokenize_reader(source.as_bytes()).unwrap();
+        let expected_text = ptemplator::reconstruct_text(&tokens);
+        let actual_text = fs::read_to_string(&file_path).unwrap();
         assert_eq!(expected_text, actual_text);
     }
 
     #[test]
-    fn test_arg_parsing() {
-        let args: Vec<String> = ["--var1", "1", "--var2", "\"2\""]
-            .iter()
-            .map(|each| each.to_string())
-            .collect();
-        let symbols = symbol_table_from_args(&args);
-        assert_eq!(symbols.get_variable("var2").unwrap(), "2".to_string());
-    }
-
-    fn read_file_as_string(path: &path::Path) -> String {
-        let file = File::open(path).unwrap();
-        let reader = BufReader::new(file);
-        reader
-            .lines()
-            .map(|l| l.unwrap())
-            .collect::<Vec<String>>()
-            .join("\n")
+    fn test_check_template() {
+        let source = "${var1}\n\t${var2}\n${endif} ${var3}";
+        let errors = check_template(
+            source,
+            &ptemplator::SymbolTable::new(&[("var1", "1")]),
+            &Default::default(),
+        );
+        assert!(matches!(
+            errors.as_slice(),
+            [
+                ptemplator::ParseError::VariableNotFound(_),
+                ptemplator::ParseError::UnexpectedBlockEnd(_),
+                ptemplator::ParseError::VariableNotFound(_)
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_write_in_place_with_backup() {
+        let dir = std::env::temp_dir().join("ptemplator_test_write_in_place");
+        std::fs::create_dir_all(&dir).unwrap();
+        let file_path = dir.join("template.txt");
+        std::fs::write(&file_path, "${var1}").unwrap();
+        write_output("1\n", &Output::InPlace { backup: true }, &file_path).unwrap();
+        assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "1\n");
+        assert_eq!(
+            std::fs::read_to_string(dir.join("template.txt.bak")).unwrap(),
+            "${var1}"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_render_dir() {
+        let dir = std::env::temp_dir().join("ptemplator_test_render_dir");
+        let src_dir = dir.join("src");
+        std::fs::create_dir_all(src_dir.join("sub")).unwrap();
+        std::fs::write(src_dir.join("sub/a.tmpl"), "name: ${name}").unwrap();
+        std::fs::write(src_dir.join("b.txt"), "${name}").unwrap();
+        let vars = crate::cli::parse_args(&[
+            "render-dir".to_string(),
+            "src".to_string(),
+            "out".to_string(),
+            "--name".to_string(),
+            "x".to_string(),
+        ]);
+        let vars = match vars {
+            Ok(crate::Command::RenderDir { vars, .. }) => vars,
+            _ => panic!("Expected a render-dir command"),
+        };
+        render_dir(
+            &src_dir,
+            &dir.join("out"),
+            &vars,
+            Some("*.tmpl"),
+            2,
+            &Default::default(),
+            &crate::Reporter::new(Default::default()),
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("out/sub/a.tmpl")).unwrap(),
+            "name: x"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("out/b.txt")).unwrap(),
+            "${name}"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_render_templates() {
+        let dir = std::env::temp_dir().join("ptemplator_test_render_templates");
+        std::fs::create_dir_all(&dir).unwrap();
+        let templates: Vec<path::PathBuf> =
+            (0..4).map(|i| dir.join(format!("{}.txt", i))).collect();
+        for (i, template) in templates.iter().enumerate() {
+            std::fs::write(template, format!("{} ${{name}}", i)).unwrap();
+        }
+        let vars = match crate::cli::parse_args(&["t.txt".to_string(), "--name=x".to_string()]) {
+            Ok(crate::Command::Render { vars, .. }) => vars,
+            _ => panic!("Expected a render command"),
+        };
+        render(
+            &templates,
+            &vars,
+            &Output::InPlace { backup: false },
+            &crate::Prompt::Never,
+            2,
+            &Default::default(),
+            &crate::Reporter::new(Default::default()),
+        );
+        for (i, template) in templates.iter().enumerate() {
+            assert_eq!(
+                std::fs::read_to_string(template).unwrap(),
+                format!("{} x", i)
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,58 @@
+/// Line break written at the end of the lines of the output
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    /// Line ending of the platform (`\r\n` on Windows)
+    pub fn native() -> Self {
+        if cfg!(windows) {
+            LineEnding::Crlf
+        } else {
+            LineEnding::Lf
+        }
+    }
+
+    /// Line ending of the first line of `text`, `None` if it has a single line
+    pub fn detect(text: &[u8]) -> Option<Self> {
+        let end = text.iter().position(|byte| *byte == b'\n')?;
+        if text[..end].ends_with(b"\r") {
+            Some(LineEnding::Crlf)
+        } else {
+            Some(LineEnding::Lf)
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+
+    /// `text` with every line break (`\n` or `\r\n`) replaced by this line ending
+    pub fn convert(&self, text: &str) -> String {
+        let text = text.replace("\r\n", "\n");
+        match self {
+            LineEnding::Lf => text,
+            LineEnding::Crlf => text.replace('\n', self.as_str()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LineEnding;
+
+    #[test]
+    fn test_line_endings() {
+        assert_eq!(LineEnding::detect(b"a\r\nb\n"), Some(LineEnding::Crlf));
+        assert_eq!(LineEnding::detect(b"a\nb\r\n"), Some(LineEnding::Lf));
+        assert_eq!(LineEnding::detect(b"a\r"), None);
+        assert_eq!(LineEnding::Crlf.convert("a\nb\r\n\n"), "a\r\nb\r\n\r\n");
+        assert_eq!(LineEnding::Lf.convert("a\nb\r\n\r"), "a\nb\n\r");
+    }
+}
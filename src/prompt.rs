@@ -0,0 +1,106 @@
+use std::io::{self, BufRead, Write};
+
+use ptemplator::{
+    parse_tokens_with_delimiters, to_output_tokens_with_errors, tokenize_text, variable_uses,
+    Identifier, SymbolTable, TextGenError, TextGenOptions, Value,
+};
+
+/// Ask for the value of every variable the template would fail without (showing where it's first
+/// used), until there are none left or `input` ends. Returns the table with the answers added
+/// along with the answers in the order they were given
+pub fn ask_missing<R: BufRead, W: Write>(
+    source: &str,
+    symbol_table: SymbolTable,
+    options: &TextGenOptions,
+    mut input: R,
+    mut prompts: W,
+) -> io::Result<(SymbolTable, Vec<(Identifier, String)>)> {
+    // templates that can't be tokenized are reported when they are rendered
+    let symbols = match tokenize_text(source) {
+        Ok(tokens) => parse_tokens_with_delimiters(&tokens, &options.delimiters).0,
+        Err(_) => return Ok((symbol_table, vec![])),
+    };
+    let uses = variable_uses(&symbols);
+    let mut symbol_table = symbol_table;
+    let mut answers: Vec<(Identifier, String)> = vec![];
+    // answers can reveal more missing variables (items of a loop, spread files)
+    loop {
+        let (_, errors) = to_output_tokens_with_errors(&symbols, &symbol_table, options);
+        let mut missing = uses.iter().filter(|variable| {
+            // there is no way to answer a part of a structured value
+            !variable.identifier.contains('.')
+                && errors.contains(&TextGenError::VariableNotFound(variable.range))
+        });
+        let variable = match missing.next() {
+            Some(variable) => variable,
+            None => return Ok((symbol_table, answers)),
+        };
+        if answers
+            .iter()
+            .any(|(identifier, _)| identifier == &variable.identifier)
+        {
+            return Ok((symbol_table, answers));
+        }
+        write!(
+            prompts,
+            "{} ({} at {}:{}): ",
+            variable.identifier,
+            variable.kind,
+            variable.range.start_pos.line + 1,
+            variable.range.start_pos.column + 1
+        )?;
+        prompts.flush()?;
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            return Ok((symbol_table, answers));
+        }
+        let answer = answer.trim_end_matches(['\n', '\r']).to_string();
+        symbol_table = symbol_table.with_values([(
+            variable.identifier.to_string(),
+            Value::from(answer.as_str()),
+        )]);
+        answers.push((variable.identifier.to_string(), answer));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ask_missing;
+    use ptemplator::SymbolTable;
+
+    #[test]
+    fn test_ask_missing() {
+        let source = "${var1} ${if var3}${var4}${endif}\n${for i in items}${i}${endfor} ${var1}";
+        let mut prompts = vec![];
+        let (symbol_table, answers) = ask_missing(
+            source,
+            SymbolTable::new(&[("var2", "2")]),
+            &Default::default(),
+            &b"1\na,b\n"[..],
+            &mut prompts,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(prompts).unwrap(),
+            "var1 (replace at 1:1): items (loop at 2:1): "
+        );
+        assert_eq!(
+            answers,
+            vec![
+                ("var1".to_string(), "1".to_string()),
+                ("items".to_string(), "a,b".to_string())
+            ]
+        );
+        assert_eq!(symbol_table.get_variable("items").unwrap(), "a,b");
+        // input ending leaves the rest undefined
+        let (_, answers) = ask_missing(
+            source,
+            SymbolTable::new::<&str>(&[]),
+            &Default::default(),
+            &b""[..],
+            vec![],
+        )
+        .unwrap();
+        assert!(answers.is_empty());
+    }
+}
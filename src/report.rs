@@ -0,0 +1,235 @@
+use std::env;
+use std::io::{self, IsTerminal};
+use std::path;
+use std::process::ExitCode;
+
+use ptemplator::{Diagnostic, ParseError, TextGenError};
+
+use crate::cli::{CliError, ErrorFormat, USAGE};
+
+// Exit codes of the failures
+const TEMPLATE_ERROR: u8 = 1;
+const USAGE_ERROR: u8 = 2;
+const SYNTAX_ERROR: u8 = 3;
+const MISSING_VARIABLE: u8 = 4;
+const IO_ERROR: u8 = 5;
+
+/// Writes the errors to stderr in the format asked for
+pub struct Reporter {
+    format: ErrorFormat,
+    color: bool,
+}
+
+impl Reporter {
+    pub fn new(format: ErrorFormat) -> Self {
+        Reporter {
+            format,
+            // colors are only used when a person is reading the errors
+            color: io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none(),
+        }
+    }
+
+    /// Report the problems of the template at `file_path` (with its text as `source`, if it's
+    /// known). The exit code is the one of the first problem
+    pub fn diagnostics(
+        &self,
+        diagnostics: Vec<Diagnostic>,
+        file_path: &path::Path,
+        source: &str,
+    ) -> ExitCode {
+        let file_path = display_path(file_path);
+        let exit_code = diagnostics.first().map_or(TEMPLATE_ERROR, exit_code);
+        for diagnostic in diagnostics {
+            let diagnostic = diagnostic.in_file(file_path);
+            match self.format {
+                ErrorFormat::Human => eprintln!("{}\n", diagnostic.report(source, self.color)),
+                ErrorFormat::Json => eprintln!("{}", diagnostic.to_json()),
+            }
+        }
+        ExitCode::from(exit_code)
+    }
+
+    /// Report a failure to read or write a file that is not about the content of a template
+    /// (writing the output, reading a directory)
+    pub fn io_failure(&self, file_path: Option<&path::Path>, message: &str) -> ExitCode {
+        self.failure(file_path, "io_error", message);
+        ExitCode::from(IO_ERROR)
+    }
+
+    /// Report a problem with the arguments found once they are used (invalid vars files, flags
+    /// that don't apply to the input)
+    pub fn usage_failure(&self, message: &str) -> ExitCode {
+        self.failure(None, "usage_error", message);
+        ExitCode::from(USAGE_ERROR)
+    }
+
+    /// Report the arguments that couldn't be parsed, along with the usage when they are wrong
+    pub fn argument_failure(&self, err: &CliError) -> ExitCode {
+        match err {
+            CliError::FailedToReadValue(file_path, err) => {
+                let message = format!("Failed to read value ({})", err);
+                self.io_failure(Some(file_path), &message)
+            }
+            err => {
+                let exit_code = self.usage_failure(&err.to_string());
+                self.note(&format!("\n{}", USAGE));
+                exit_code
+            }
+        }
+    }
+
+    fn failure(&self, file_path: Option<&path::Path>, code: &str, message: &str) {
+        let file_path = file_path.map(display_path);
+        match self.format {
+            ErrorFormat::Human => match file_path {
+                Some(file_path) => eprintln!("{}: {}", file_path.display(), message),
+                None => eprintln!("{}", message),
+            },
+            ErrorFormat::Json => eprintln!("{}", failure_json(file_path, code, message)),
+        }
+    }
+
+    /// Progress messages are only meant for people
+    pub fn note(&self, message: &str) {
+        if self.format == ErrorFormat::Human {
+            eprintln!("{}", message);
+        }
+    }
+}
+
+fn failure_json(file_path: Option<&path::Path>, code: &str, message: &str) -> serde_json::Value {
+    serde_json::json!({
+        "file": file_path.map(|file_path| file_path.display().to_string()),
+        "line": null,
+        "column": null,
+        "end_line": null,
+        "end_column": null,
+        "code": code,
+        "message": message,
+    })
+}
+
+// Template is read from stdin when the path is `-`
+fn display_path(file_path: &path::Path) -> &path::Path {
+    if file_path == path::Path::new(crate::STDIN_PATH) {
+        path::Path::new("<stdin>")
+    } else {
+        file_path
+    }
+}
+
+fn exit_code(diagnostic: &Diagnostic) -> u8 {
+    match diagnostic {
+        Diagnostic::Parse {
+            error:
+                ParseError::InvalidFilePath
+                | ParseError::FailedToOpenFile
+                | ParseError::FailedToReadLine(_),
+            ..
+        }
+        | Diagnostic::TextGen {
+            error: TextGenError::FailedToReadFile(_),
+            ..
+        } => IO_ERROR,
+        Diagnostic::Parse {
+            error: ParseError::VariableNotFound(_) | ParseError::FileNotFound(_),
+            ..
+        }
+        | Diagnostic::TextGen {
+            error: TextGenError::VariableNotFound(_),
+            ..
+        } => MISSING_VARIABLE,
+        Diagnostic::Parse { .. }
+        | Diagnostic::TextGen {
+            error:
+                TextGenError::InvalidInclude(_, _)
+                | TextGenError::InvalidValue(_, _)
+                | TextGenError::UndefinedMacro(_)
+                | TextGenError::WrongArgumentCount(_),
+            ..
+        } => SYNTAX_ERROR,
+        Diagnostic::TextGen { .. } => TEMPLATE_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path;
+    use std::process::ExitCode;
+
+    use ptemplator::{Diagnostic, ParseError, Position, Range, TextGenError};
+
+    use crate::cli::CliError;
+
+    use super::{
+        exit_code, failure_json, Reporter, IO_ERROR, MISSING_VARIABLE, SYNTAX_ERROR, USAGE_ERROR,
+    };
+
+    #[test]
+    fn test_exit_code() {
+        let range = Range {
+            start_pos: Position { line: 0, column: 0 },
+            end_pos: Position { line: 0, column: 4 },
+        };
+        let exit_codes: Vec<u8> = [
+            Diagnostic::from(ParseError::UnclosedBlock(range)),
+            Diagnostic::from(ParseError::VariableNotFound(range)),
+            Diagnostic::from(TextGenError::VariableNotFound(range)),
+            Diagnostic::from(TextGenError::FailedToReadFile(range)),
+            Diagnostic::from(ParseError::FailedToOpenFile),
+            Diagnostic::from(TextGenError::IncludeCycle(range)),
+        ]
+        .iter()
+        .map(exit_code)
+        .collect();
+        assert_eq!(
+            exit_codes,
+            vec![
+                SYNTAX_ERROR,
+                MISSING_VARIABLE,
+                MISSING_VARIABLE,
+                IO_ERROR,
+                IO_ERROR,
+                1
+            ]
+        );
+    }
+
+    #[test]
+    fn test_failure_exit_codes() {
+        let reporter = Reporter::new(Default::default());
+        assert_eq!(
+            reporter.usage_failure("stdin can't be watched"),
+            ExitCode::from(USAGE_ERROR)
+        );
+        assert_eq!(
+            reporter.io_failure(None, "Failed to read directory"),
+            ExitCode::from(IO_ERROR)
+        );
+        let err = CliError::FailedToReadValue(path::PathBuf::from("cert.pem"), "missing".into());
+        assert_eq!(reporter.argument_failure(&err), ExitCode::from(IO_ERROR));
+        assert_eq!(
+            reporter.argument_failure(&CliError::MissingTemplate),
+            ExitCode::from(USAGE_ERROR)
+        );
+    }
+
+    #[test]
+    fn test_failure_json() {
+        assert_eq!(
+            failure_json(
+                Some(path::Path::new("out.txt")),
+                "io_error",
+                "Failed to write output"
+            )
+            .to_string(),
+            "{\"code\":\"io_error\",\"column\":null,\"end_column\":null,\"end_line\":null,\
+             \"file\":\"out.txt\",\"line\":null,\"message\":\"Failed to write output\"}"
+        );
+        assert_eq!(
+            failure_json(None, "usage_error", "stdin can't be watched").to_string(),
+            "{\"code\":\"usage_error\",\"column\":null,\"end_column\":null,\"end_line\":null,\
+             \"file\":null,\"line\":null,\"message\":\"stdin can't be watched\"}"
+        );
+    }
+}
@@ -0,0 +1,124 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::front::Identifier;
+use crate::value::Value;
+
+/// Source of the values of variables. Only the root of dotted identifiers (`config` of
+/// `${config.db.host}`) is resolved, the rest of the path is looked up in the value
+pub trait Resolver: Send + Sync {
+    fn resolve(&self, identifier: &str) -> Option<Cow<'_, str>>;
+
+    /// Structured value of the variable, resolvers that only have text don't need to implement it
+    fn resolve_value(&self, identifier: &str) -> Option<Value> {
+        self.resolve(identifier)
+            .map(|value| Value::from(value.as_ref()))
+    }
+}
+
+impl Resolver for HashMap<Identifier, Value> {
+    fn resolve(&self, identifier: &str) -> Option<Cow<'_, str>> {
+        self.get(identifier)
+            .map(|value| Cow::Owned(value.to_string()))
+    }
+
+    fn resolve_value(&self, identifier: &str) -> Option<Value> {
+        self.get(identifier).cloned()
+    }
+}
+
+impl Resolver for Arc<dyn Resolver> {
+    fn resolve(&self, identifier: &str) -> Option<Cow<'_, str>> {
+        self.as_ref().resolve(identifier)
+    }
+
+    fn resolve_value(&self, identifier: &str) -> Option<Value> {
+        self.as_ref().resolve_value(identifier)
+    }
+}
+
+/// Variables of the environment
+pub struct EnvResolver;
+
+impl Resolver for EnvResolver {
+    fn resolve(&self, identifier: &str) -> Option<Cow<'_, str>> {
+        std::env::var(identifier).ok().map(Cow::Owned)
+    }
+}
+
+/// Resolvers layered on top of each other, the first one that has a variable wins
+#[derive(Default)]
+pub struct ChainResolver {
+    resolvers: Vec<Box<dyn Resolver>>,
+}
+
+impl ChainResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `resolver` below the ones added so far
+    pub fn with<R: Resolver + 'static>(mut self, resolver: R) -> Self {
+        self.resolvers.push(Box::new(resolver));
+        self
+    }
+}
+
+impl Resolver for ChainResolver {
+    fn resolve(&self, identifier: &str) -> Option<Cow<'_, str>> {
+        self.resolvers
+            .iter()
+            .find_map(|resolver| resolver.resolve(identifier))
+    }
+
+    fn resolve_value(&self, identifier: &str) -> Option<Value> {
+        self.resolvers
+            .iter()
+            .find_map(|resolver| resolver.resolve_value(identifier))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::collections::HashMap;
+
+    use super::{ChainResolver, EnvResolver, Resolver};
+    use crate::value::Value;
+
+    // Computed values, like a library user would back variables with
+    struct Upper;
+
+    impl Resolver for Upper {
+        fn resolve(&self, identifier: &str) -> Option<Cow<'_, str>> {
+            Some(Cow::Owned(identifier.to_uppercase()))
+        }
+    }
+
+    #[test]
+    fn test_chain_resolver() {
+        std::env::set_var("PTEMPLATOR_TEST_RESOLVER_VAR", "from env");
+        let first = HashMap::from([("var1".to_string(), Value::from("1"))]);
+        let second = HashMap::from([
+            ("var1".to_string(), Value::from("2")),
+            ("items".to_string(), Value::List(vec![Value::from("a")])),
+        ]);
+        let chain = ChainResolver::new()
+            .with(first)
+            .with(second)
+            .with(EnvResolver)
+            .with(Upper);
+        assert_eq!(chain.resolve("var1").unwrap(), "1");
+        assert_eq!(
+            chain.resolve_value("items").unwrap(),
+            Value::List(vec![Value::from("a")])
+        );
+        assert_eq!(
+            chain.resolve("PTEMPLATOR_TEST_RESOLVER_VAR").unwrap(),
+            "from env"
+        );
+        assert_eq!(chain.resolve("other").unwrap(), "OTHER");
+        assert!(ChainResolver::new().resolve("var1").is_none());
+    }
+}
@@ -0,0 +1,162 @@
+use std::fmt;
+
+use crate::back::TextGenOptions;
+use crate::diagnostic::Diagnostic;
+use crate::front::{
+    parse_tokens_with_errors, reconstruct_text, tokenize_text, Position, Symbol, SymbolTable,
+};
+use crate::render_with_diagnostics;
+
+/// How the output of a text differs from the text
+#[derive(Debug, PartialEq)]
+pub enum RoundtripMismatch {
+    /// The tokens of the text don't give it back, `position` is where the output first differs
+    Tokens { position: Position, output: String },
+    /// The output of a text without tags (or escapes) isn't the text
+    Output { position: Position, output: String },
+    /// A text without tags failed to render
+    Failed(Vec<Diagnostic>),
+}
+
+impl fmt::Display for RoundtripMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tokens { position, .. } => write!(
+                f,
+                "{}:{}: text of the tokens differs from the source",
+                position.line + 1,
+                position.column + 1
+            ),
+            Self::Output { position, .. } => write!(
+                f,
+                "{}:{}: output differs from the source",
+                position.line + 1,
+                position.column + 1
+            ),
+            Self::Failed(diagnostics) => {
+                write!(f, "text without tags failed to render")?;
+                for diagnostic in diagnostics {
+                    write!(f, "\n  {}", diagnostic)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for RoundtripMismatch {}
+
+/// Check that `reconstruct_text` gives back `text` from its tokens byte for byte and, when the text
+/// has no tags (or escaped delimiters), that rendering it does too
+pub fn verify_roundtrip(text: &str) -> Result<(), RoundtripMismatch> {
+    let tokens = tokenize_text(text).map_err(|err| RoundtripMismatch::Failed(vec![err.into()]))?;
+    let output = reconstruct_text(&tokens);
+    if let Some(position) = first_difference(text, &output) {
+        return Err(RoundtripMismatch::Tokens { position, output });
+    }
+    let (symbols, errors) = parse_tokens_with_errors(&tokens);
+    if !errors.is_empty() || !symbols.iter().all(is_source_text) {
+        return Ok(());
+    }
+    let symbol_table = SymbolTable::new::<&str>(&[]);
+    let output =
+        render_with_diagnostics(text.as_bytes(), &symbol_table, &TextGenOptions::default())
+            .map_err(RoundtripMismatch::Failed)?;
+    match first_difference(text, &output) {
+        Some(position) => Err(RoundtripMismatch::Output { position, output }),
+        None => Ok(()),
+    }
+}
+
+// Whether `symbol` is written out as it is in the source (it isn't a tag or an escaped delimiter)
+fn is_source_text(symbol: &Symbol) -> bool {
+    match symbol {
+        Symbol::Word { text, range } => {
+            range.end_pos.column - range.start_pos.column == text.chars().count()
+        }
+        _ => false,
+    }
+}
+
+// Position in `expected` where `actual` first differs from it
+fn first_difference(expected: &str, actual: &str) -> Option<Position> {
+    if expected == actual {
+        return None;
+    }
+    let mut position = Position { line: 0, column: 0 };
+    let mut actual = actual.chars();
+    for c in expected.chars() {
+        if actual.next() != Some(c) {
+            break;
+        }
+        if c == '\n' {
+            position = Position {
+                line: position.line + 1,
+                column: 0,
+            };
+        } else {
+            position.column += 1;
+        }
+    }
+    Some(position)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{verify_roundtrip, RoundtripMismatch};
+    use crate::front::Position;
+
+    #[test]
+    fn test_verify_roundtrip() {
+        let texts = [
+            "",
+            "\n",
+            "plain text\r\n\r\n",
+            "  indented\t$ { } \\ # |\n\n  trailing  \n",
+            "é😀 a\u{a0}b\u{2028}c\n",
+            "mixed\nline\r\nbreaks\r",
+            "${name} and ${if a}${endif}",
+            "${",
+            "\\${a}",
+        ];
+        for text in texts {
+            assert_eq!(verify_roundtrip(text), Ok(()), "{:?}", text);
+        }
+        // texts made of pieces chosen from a fixed seed
+        let pieces = [
+            "a", "b c", " ", "  ", "\t", "\n", "\r\n", "\r", "$", "{", "}", "${", "\\", "é", "|",
+            ":", "#", "\u{3000}",
+        ];
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        for _ in 0..5000 {
+            let mut text = String::new();
+            for _ in 0..seed % 24 {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                text.push_str(pieces[(seed % pieces.len() as u64) as usize]);
+            }
+            assert_eq!(verify_roundtrip(&text), Ok(()), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_corpus() {
+        for entry in fs::read_dir("./test_corpus").unwrap() {
+            let path = entry.unwrap().path();
+            let text = fs::read_to_string(&path).unwrap();
+            assert_eq!(verify_roundtrip(&text), Ok(()), "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn test_mismatch_display() {
+        let mismatch = RoundtripMismatch::Output {
+            position: Position { line: 1, column: 4 },
+            output: "a\nbcd".to_string(),
+        };
+        assert_eq!(mismatch.to_string(), "2:5: output differs from the source");
+    }
+}
@@ -0,0 +1,224 @@
+use std::ops;
+
+use crate::back::TextGenOptions;
+use crate::diagnostic::Diagnostic;
+use crate::front::{split_line_break, tokenize_line, Position, Range, SymbolTable, Token};
+use crate::newline::LineEnding;
+use crate::render_tokens;
+
+/// Template being edited (in an editor). Each line keeps its tokens, so an edit only tokenizes the
+/// lines it changes again. Parsing isn't incremental, `render` parses the whole template
+pub struct Session {
+    lines: Vec<Line>,
+}
+
+// Text of a line, the line break ending it (empty for the last line) and its tokens, as if it was
+// the first line
+struct Line {
+    text: String,
+    line_break: String,
+    tokens: Vec<Token>,
+}
+
+impl Line {
+    fn new(text: &str, line_break: &str) -> Self {
+        Line {
+            text: text.to_string(),
+            line_break: line_break.to_string(),
+            tokens: tokenize_line(&format!("{}{}", text, line_break), 0),
+        }
+    }
+
+    // Lines of `text`, the last one ends with `line_break`
+    fn split(text: &str, line_break: &str) -> Vec<Line> {
+        let mut lines: Vec<Line> = text
+            .split_inclusive('\n')
+            .map(|line| {
+                let (text, line_break) = split_line_break(line);
+                Line::new(text, line_break)
+            })
+            .collect();
+        if text.is_empty() || text.ends_with('\n') {
+            lines.push(Line::new("", line_break));
+        } else if let Some(last) = lines.last_mut() {
+            *last = Line::new(&last.text, line_break);
+        }
+        lines
+    }
+
+    fn length(&self) -> usize {
+        self.text.chars().count()
+    }
+}
+
+impl Session {
+    pub fn new(text: &str) -> Self {
+        Session {
+            lines: Line::split(text, ""),
+        }
+    }
+
+    /// Replace the text in `range` with `new_text`. Positions past the end of a line (or of the
+    /// text) are taken as its end. The lines that were tokenized again (the ones the new text is
+    /// on) are returned, the tokens of the rest are kept
+    pub fn apply_edit(&mut self, range: Range, new_text: &str) -> ops::Range<usize> {
+        let start = self.clamp(range.start_pos);
+        let end = self.clamp(range.end_pos);
+        let end = if (end.line, end.column) < (start.line, start.column) {
+            start
+        } else {
+            end
+        };
+        let prefix: String = self.lines[start.line]
+            .text
+            .chars()
+            .take(start.column)
+            .collect();
+        let end_line = &self.lines[end.line];
+        let suffix: String = end_line.text.chars().skip(end.column).collect();
+        let text = format!("{}{}{}", prefix, new_text, suffix);
+        let lines = Line::split(&text, &end_line.line_break);
+        let changed = start.line..start.line + lines.len();
+        self.lines.splice(start.line..=end.line, lines);
+        changed
+    }
+
+    fn clamp(&self, pos: Position) -> Position {
+        match self.lines.get(pos.line) {
+            Some(line) => Position {
+                line: pos.line,
+                column: pos.column.min(line.length()),
+            },
+            None => {
+                let line = self.lines.len() - 1;
+                Position {
+                    line,
+                    column: self.lines[line].length(),
+                }
+            }
+        }
+    }
+
+    /// Text of the template
+    pub fn text(&self) -> String {
+        self.lines
+            .iter()
+            .map(|line| format!("{}{}", line.text, line.line_break))
+            .collect()
+    }
+
+    /// Tokens of the whole template
+    pub fn tokens(&self) -> Vec<Token> {
+        self.lines
+            .iter()
+            .enumerate()
+            .flat_map(|(i, line)| line.tokens.iter().map(move |token| on_line(token, i)))
+            .collect()
+    }
+
+    /// Output of the template along the lines of `render_with_diagnostics`. It costs as much as a
+    /// full parse and render of the template (with a copy of the tokens), only tokenizing is saved
+    pub fn render(
+        &self,
+        symbols: &SymbolTable,
+        options: &TextGenOptions,
+    ) -> Result<String, Vec<Diagnostic>> {
+        let ending = match self.lines[0].line_break.as_str() {
+            "\r\n" => LineEnding::Crlf,
+            _ => LineEnding::Lf,
+        };
+        render_tokens(&self.tokens(), vec![], ending, symbols, options)
+    }
+}
+
+// `token` of a line tokenized on its own, moved to `line`
+fn on_line(token: &Token, line: usize) -> Token {
+    let at_line = |pos: &Position| Position { line, ..*pos };
+    let range_at_line = |range: &Range| Range {
+        start_pos: at_line(&range.start_pos),
+        end_pos: at_line(&range.end_pos),
+    };
+    match token {
+        Token::Word { text, range } => Token::Word {
+            text: text.clone(),
+            range: range_at_line(range),
+        },
+        Token::Whitespace { text, range } => Token::Whitespace {
+            text: text.clone(),
+            range: range_at_line(range),
+        },
+        Token::Punctuation { value, pos } => Token::Punctuation {
+            value: *value,
+            pos: at_line(pos),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Session;
+    use crate::back::TextGenOptions;
+    use crate::front::{tokenize_text, Position, Range, SymbolTable};
+
+    fn range(start: (usize, usize), end: (usize, usize)) -> Range {
+        Range {
+            start_pos: Position {
+                line: start.0,
+                column: start.1,
+            },
+            end_pos: Position {
+                line: end.0,
+                column: end.1,
+            },
+        }
+    }
+
+    #[test]
+    fn test_session_edits() {
+        let symbols = SymbolTable::new(&[("name", "world"), ("flag", "x")]);
+        let options = TextGenOptions::default();
+        let mut session = Session::new("Hello ${name}\r\nbye\r\n");
+        assert_eq!(
+            session.render(&symbols, &options).unwrap(),
+            "Hello world\r\nbye\r\n"
+        );
+        // a block opened over several lines, the lines after the edit keep their tokens
+        assert_eq!(
+            session.apply_edit(range((0, 0), (0, 0)), "${if flag}\r\n"),
+            0..2
+        );
+        assert_eq!(
+            session.apply_edit(range((2, 3), (2, 3)), "\r\n${endif}"),
+            2..4
+        );
+        assert_eq!(
+            session.text(),
+            "${if flag}\r\nHello ${name}\r\nbye\r\n${endif}\r\n"
+        );
+        assert_eq!(session.tokens(), tokenize_text(&session.text()).unwrap());
+        assert_eq!(
+            session.render(&symbols, &options).unwrap(),
+            "Hello world\r\nbye\r\n"
+        );
+        // edits spanning lines, and positions past the end
+        assert_eq!(session.apply_edit(range((1, 6), (2, 1)), "${flag} b"), 1..2);
+        session.apply_edit(range((9, 0), (9, 4)), "!");
+        assert_eq!(
+            session.text(),
+            "${if flag}\r\nHello ${flag} bye\r\n${endif}\r\n!"
+        );
+        assert_eq!(
+            session.render(&symbols, &options).unwrap(),
+            "Hello x bye\r\n!"
+        );
+        // the line breaks are kept as they are typed
+        session.apply_edit(range((1, 17), (1, 17)), "\n");
+        assert_eq!(
+            session.text(),
+            "${if flag}\r\nHello ${flag} bye\n\r\n${endif}\r\n!"
+        );
+        assert_eq!(session.tokens(), tokenize_text(&session.text()).unwrap());
+        session.apply_edit(range((0, 0), (0, 10)), "${if}");
+        assert!(session.render(&symbols, &options).is_err());
+    }
+}
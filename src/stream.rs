@@ -0,0 +1,207 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::back::{Generator, TextGenOptions};
+use crate::diagnostic::Diagnostic;
+use crate::front::{
+    append_text, is_block_tag, parse_tokens_with_delimiters, tokenize_line, Delimiters, ParseError,
+    Position, SymbolTable, Token,
+};
+use crate::newline::LineEnding;
+
+#[derive(Debug)]
+pub enum StreamError {
+    /// Problems of the template, output stops before the first part that has one
+    Template(Vec<Diagnostic>),
+    FailedToWrite(io::Error),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Template(diagnostics) => {
+                let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+                write!(f, "{}", messages.join("\n"))
+            }
+            Self::FailedToWrite(err) => write!(f, "failed to write output ({})", err),
+        }
+    }
+}
+
+impl std::error::Error for StreamError {}
+
+impl From<io::Error> for StreamError {
+    fn from(err: io::Error) -> Self {
+        StreamError::FailedToWrite(err)
+    }
+}
+
+/// Render `template` into `output` a line at a time. Lines are only kept until the blocks opened
+/// in them are closed, so large templates are never fully in memory. Like
+/// `render_with_diagnostics` the whole template is checked, but nothing more is written after the
+/// first problem
+pub fn render_stream<R: BufRead, W: Write>(
+    mut template: R,
+    mut output: W,
+    symbols: &SymbolTable,
+    options: &TextGenOptions,
+) -> Result<(), StreamError> {
+    let mut generator = Generator::new(options);
+    let mut current = Position { line: 0, column: 0 };
+    let mut chunk = vec![];
+    let mut depth = 0;
+    let mut diagnostics = vec![];
+    // generated lines end like the first line of the template
+    let mut ending = None;
+    let mut line = vec![];
+    for i in 0.. {
+        line.clear();
+        let is_done = match template.read_until(b'\n', &mut line) {
+            Ok(0) => true,
+            Ok(_) => {
+                ending.get_or_insert(LineEnding::detect(&line).unwrap_or_default());
+                let text =
+                    String::from_utf8(line.clone()).map_err(|_| ParseError::FailedToReadLine(i));
+                match text.map(|text| tokenize_line(&text, i)) {
+                    Ok(tokens) => {
+                        depth = (depth + block_depth(&tokens, &options.delimiters)).max(0);
+                        chunk.extend(tokens);
+                    }
+                    Err(err) => diagnostics.push(Diagnostic::from(err)),
+                }
+                false
+            }
+            Err(_) => {
+                diagnostics.push(ParseError::FailedToReadLine(i).into());
+                true
+            }
+        };
+        // blocks that are never closed are reported once the whole template is read
+        if depth > 0 && !is_done {
+            continue;
+        }
+        let (symbols_of_chunk, parse_errors) =
+            parse_tokens_with_delimiters(&chunk, &options.delimiters);
+        let (output_tokens, text_gen_errors) = generator.generate(&symbols_of_chunk, symbols);
+        diagnostics.extend(parse_errors.into_iter().map(Diagnostic::from));
+        diagnostics.extend(text_gen_errors.into_iter().map(Diagnostic::from));
+        if diagnostics.is_empty() {
+            let mut text = String::new();
+            let line_break = ending.unwrap_or_default().as_str();
+            append_text(&output_tokens, &mut current, line_break, &mut text);
+            if let Some(ending) = options.line_ending {
+                text = ending.convert(&text);
+            }
+            output.write_all(text.as_bytes())?;
+        }
+        chunk.clear();
+        if is_done {
+            break;
+        }
+    }
+    output.flush()?;
+    if diagnostics.is_empty() {
+        Ok(())
+    } else {
+        Err(StreamError::Template(diagnostics))
+    }
+}
+
+// Number of blocks opened by the tags of a line minus the ones closed
+fn block_depth(tokens: &[Token], delimiters: &Delimiters) -> isize {
+    (0..tokens.len())
+        .map(|i| {
+            let tokens = &tokens[i..];
+            let is_tag = |keyword| is_block_tag(tokens, keyword, delimiters);
+            if is_tag("if") || is_tag("for") || is_tag("def") {
+                1
+            } else if is_tag("endif") || is_tag("endfor") || is_tag("enddef") {
+                -1
+            } else {
+                0
+            }
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render_stream, StreamError};
+    use crate::back::TextGenOptions;
+    use crate::diagnostic::Diagnostic;
+    use crate::front::{ParseError, SymbolTable};
+    use crate::render_with_diagnostics;
+
+    fn stream_text(template: &str, symbols: &SymbolTable) -> Result<String, StreamError> {
+        let mut output = vec![];
+        render_stream(
+            template.as_bytes(),
+            &mut output,
+            symbols,
+            &TextGenOptions::default(),
+        )?;
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_stream_same_as_render() {
+        let symbols = SymbolTable::new(&[
+            ("var1", "1"),
+            ("items", "a,b"),
+            ("file", "./test_corpus/block.yaml"),
+        ]);
+        let templates = [
+            "Hello ${var1}!\n  ${var1} ${var1}",
+            "a\n${if var2}\nb\n${endif}\nc ${var1}",
+            "start\n${for i in items}\n  - ${i}\n${endfor}\n\n${if var1}${var1}${endif} end",
+            "x: ${...file}\n${for i in items}${i}${endfor} ${var1}\nlast",
+            "${if var1}\n${for i in items}\n${i}\n${endfor}\n${endif}\n",
+            "${def f(x)}\n<${x}>\n${enddef}\n${f(a)} ${f(b)}\n",
+        ];
+        for template in templates {
+            let expected =
+                render_with_diagnostics(template.as_bytes(), &symbols, &Default::default());
+            assert_eq!(
+                stream_text(template, &symbols).unwrap(),
+                expected.unwrap(),
+                "{:?}",
+                template
+            );
+        }
+        // a macro called before it's defined fails the same way
+        let template = "${f(a)}\n${def f(x)}\n<${x}>\n${enddef}\n";
+        let expected = render_with_diagnostics(template.as_bytes(), &symbols, &Default::default());
+        match stream_text(template, &symbols) {
+            Err(StreamError::Template(diagnostics)) => {
+                assert_eq!(diagnostics, expected.unwrap_err())
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_stream_errors() {
+        let symbols = SymbolTable::new(&[("var1", "1")]);
+        let mut output = vec![];
+        let result = render_stream(
+            "${var1}\n${var2}\n${var1}\n${if var1}\n".as_bytes(),
+            &mut output,
+            &symbols,
+            &TextGenOptions::default(),
+        );
+        assert_eq!(String::from_utf8(output).unwrap(), "1\n");
+        match result {
+            Err(StreamError::Template(diagnostics)) => assert!(matches!(
+                diagnostics.as_slice(),
+                [
+                    Diagnostic::TextGen { .. },
+                    Diagnostic::Parse {
+                        error: ParseError::UnclosedBlock(_),
+                        ..
+                    }
+                ]
+            )),
+            _ => panic!("Expected the errors of the template"),
+        }
+    }
+}
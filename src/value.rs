@@ -0,0 +1,116 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Value bound to a variable. Values read from structured files (json, yaml, toml) can be lists
+/// and maps which are indexed with dotted paths (`${config.hosts.0}`)
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    String(String),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+impl Value {
+    /// Value at the dotted `path` relative to this value
+    pub fn get_path<'a, I: IntoIterator<Item = &'a str>>(&self, path: I) -> Option<&Value> {
+        path.into_iter()
+            .try_fold(self, |value, segment| match value {
+                Value::Map(entries) => entries.get(segment),
+                Value::List(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+                Value::String(_) => None,
+            })
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Value::String(text) => serde_json::Value::String(text.to_string()),
+            Value::List(items) => {
+                serde_json::Value::Array(items.iter().map(Value::to_json).collect())
+            }
+            Value::Map(entries) => serde_json::Value::Object(
+                entries
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_json()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+// Strings are written as is while lists and maps are written as json
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::String(text) => write!(f, "{}", text),
+            value => write!(f, "{}", value.to_json()),
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+// Scalars other than strings are kept in their json representation
+impl From<serde_json::Value> for Value {
+    fn from(value: serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::String(text) => Value::String(text),
+            serde_json::Value::Null => Value::String(String::new()),
+            serde_json::Value::Array(items) => {
+                Value::List(items.into_iter().map(Value::from).collect())
+            }
+            serde_json::Value::Object(entries) => Value::Map(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, Value::from(value)))
+                    .collect(),
+            ),
+            value => Value::String(value.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Value;
+
+    #[test]
+    fn test_get_path() {
+        let value = Value::from(
+            serde_json::from_str::<serde_json::Value>(
+                r#"{"host": "localhost", "ports": [80, 443], "db": {"user": "admin"}}"#,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            value.get_path(["host"]),
+            Some(&Value::String("localhost".to_string()))
+        );
+        assert_eq!(
+            value.get_path(["ports", "1"]),
+            Some(&Value::String("443".to_string()))
+        );
+        assert_eq!(
+            value.get_path(["db", "user"]),
+            Some(&Value::String("admin".to_string()))
+        );
+        assert_eq!(value.get_path(["db", "password"]), None);
+        assert_eq!(value.get_path(["host", "name"]), None);
+    }
+
+    #[test]
+    fn test_display() {
+        let value = Value::List(vec![Value::from("a"), Value::from("b")]);
+        assert_eq!(value.to_string(), r#"["a","b"]"#);
+        assert_eq!(Value::from("a b").to_string(), "a b");
+    }
+}
@@ -0,0 +1,173 @@
+use std::fmt;
+use std::fs;
+use std::path;
+
+use crate::front::Identifier;
+use crate::value::Value;
+
+#[derive(Debug)]
+pub enum VarsFileError {
+    UnsupportedFormat,
+    FailedToReadFile,
+    InvalidContent(String),
+    NotAMap,
+    FailedToWriteFile,
+}
+
+impl fmt::Display for VarsFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedFormat => {
+                write!(f, "Unsupported vars file format (use json, yaml or toml)")
+            }
+            Self::FailedToReadFile => {
+                write!(f, "Failed to read vars file")
+            }
+            Self::InvalidContent(message) => {
+                write!(f, "Invalid vars file: {}", message)
+            }
+            Self::NotAMap => {
+                write!(f, "Vars file must contain a map of variables")
+            }
+            Self::FailedToWriteFile => {
+                write!(f, "Failed to write vars file")
+            }
+        }
+    }
+}
+
+impl std::error::Error for VarsFileError {}
+
+/// Read the variables defined at the top level of a json, yaml or toml file
+pub fn load_vars_file(file_path: &path::Path) -> Result<Vec<(Identifier, Value)>, VarsFileError> {
+    Ok(read_variables(file_path)?
+        .into_iter()
+        .map(|(key, value)| (key, Value::from(value)))
+        .collect())
+}
+
+/// Write `variables` to a json, yaml or toml file. Variables the file already defines are kept
+/// unless they are given again
+pub fn save_vars_file(
+    file_path: &path::Path,
+    variables: &[(Identifier, String)],
+) -> Result<(), VarsFileError> {
+    let mut entries = if file_path.exists() {
+        read_variables(file_path)?
+    } else {
+        serde_json::Map::new()
+    };
+    for (identifier, value) in variables {
+        entries.insert(
+            identifier.to_string(),
+            serde_json::Value::String(value.to_string()),
+        );
+    }
+    let value = serde_json::Value::Object(entries);
+    let content = match extension(file_path) {
+        "json" => serde_json::to_string_pretty(&value).map_err(|err| err.to_string()),
+        "yaml" | "yml" => serde_yaml::to_string(&value).map_err(|err| err.to_string()),
+        "toml" => toml::to_string(&value).map_err(|err| err.to_string()),
+        _ => return Err(VarsFileError::UnsupportedFormat),
+    }
+    .map_err(VarsFileError::InvalidContent)?;
+    fs::write(file_path, content).map_err(|_| VarsFileError::FailedToWriteFile)
+}
+
+fn extension(file_path: &path::Path) -> &str {
+    file_path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+}
+
+fn read_variables(
+    file_path: &path::Path,
+) -> Result<serde_json::Map<String, serde_json::Value>, VarsFileError> {
+    let content = fs::read_to_string(file_path).map_err(|_| VarsFileError::FailedToReadFile)?;
+    let value: serde_json::Value = match extension(file_path) {
+        "json" => serde_json::from_str(&content)
+            .map_err(|err| VarsFileError::InvalidContent(err.to_string())),
+        "yaml" | "yml" => serde_yaml::from_str(&content)
+            .map_err(|err| VarsFileError::InvalidContent(err.to_string())),
+        "toml" => {
+            toml::from_str(&content).map_err(|err| VarsFileError::InvalidContent(err.to_string()))
+        }
+        _ => Err(VarsFileError::UnsupportedFormat),
+    }?;
+    match value {
+        serde_json::Value::Object(variables) => Ok(variables),
+        _ => Err(VarsFileError::NotAMap),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path;
+
+    use super::{load_vars_file, save_vars_file, VarsFileError};
+    use crate::value::Value;
+
+    fn load_sorted(file_path: &str) -> Vec<(String, Value)> {
+        let mut variables = load_vars_file(&path::PathBuf::from(file_path)).unwrap();
+        variables.sort_by(|a, b| a.0.cmp(&b.0));
+        variables
+    }
+
+    #[test]
+    fn test_load_all_formats() {
+        let expected = vec![
+            ("count".to_string(), Value::from("3")),
+            ("greeting".to_string(), Value::from("Hello world")),
+            (
+                "items".to_string(),
+                Value::List(vec![Value::from("a"), Value::from("b")]),
+            ),
+        ];
+        assert_eq!(load_sorted("./test_corpus/vars.json"), expected);
+        assert_eq!(load_sorted("./test_corpus/vars.yaml"), expected);
+        assert_eq!(load_sorted("./test_corpus/vars.toml"), expected);
+    }
+
+    #[test]
+    fn test_save_vars_file() {
+        let dir = std::env::temp_dir().join("ptemplator_test_save_vars_file");
+        std::fs::create_dir_all(&dir).unwrap();
+        for extension in ["json", "yaml", "toml"] {
+            let file_path = dir.join(format!("vars.{}", extension));
+            std::fs::copy(format!("./test_corpus/vars.{}", extension), &file_path).unwrap();
+            let answers = [
+                ("greeting".to_string(), "Hi".to_string()),
+                ("name".to_string(), "x".to_string()),
+            ];
+            save_vars_file(&file_path, &answers).unwrap();
+            let mut variables = load_vars_file(&file_path).unwrap();
+            variables.sort_by(|a, b| a.0.cmp(&b.0));
+            assert_eq!(
+                variables,
+                vec![
+                    ("count".to_string(), Value::from("3")),
+                    ("greeting".to_string(), Value::from("Hi")),
+                    (
+                        "items".to_string(),
+                        Value::List(vec![Value::from("a"), Value::from("b")]),
+                    ),
+                    ("name".to_string(), Value::from("x")),
+                ]
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unsupported_format() {
+        assert!(matches!(
+            load_vars_file(&path::PathBuf::from("./test_corpus/simple.txt")),
+            Err(VarsFileError::UnsupportedFormat)
+        ));
+        assert_eq!(
+            VarsFileError::UnsupportedFormat.to_string(),
+            "Unsupported vars file format (use json, yaml or toml)"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use std::fs;
+use std::path;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use ptemplator::{
+    glob_files, is_glob, parse_tokens_with_delimiters, tokenize_text, variable_uses, Delimiters,
+    SymbolTable, VariableKind,
+};
+
+// How often the watched files are checked
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Files the output of `template` depends on: the template itself, the vars files and the files
+/// spread into it (including the ones spread into those)
+pub fn watched_files(
+    template: &path::Path,
+    vars_files: &[path::PathBuf],
+    symbol_table: &SymbolTable,
+    delimiters: &Delimiters,
+) -> Vec<path::PathBuf> {
+    let mut files = vec![template.to_path_buf()];
+    files.extend(vars_files.iter().cloned());
+    let mut pending = vec![template.to_path_buf()];
+    while let Some(file_path) = pending.pop() {
+        for spread in spread_files(&file_path, symbol_table, delimiters) {
+            if !files.contains(&spread) {
+                files.push(spread.clone());
+                pending.push(spread);
+            }
+        }
+    }
+    files
+}
+
+// Files spread into the file at `file_path` (files that can't be parsed spread nothing)
+fn spread_files(
+    file_path: &path::Path,
+    symbol_table: &SymbolTable,
+    delimiters: &Delimiters,
+) -> Vec<path::PathBuf> {
+    let symbols = fs::read_to_string(file_path)
+        .ok()
+        .and_then(|source| tokenize_text(&source).ok())
+        .map(|tokens| parse_tokens_with_delimiters(&tokens, delimiters))
+        .filter(|(_, errors)| errors.is_empty())
+        .map(|(symbols, _)| symbols)
+        .unwrap_or_default();
+    variable_uses(&symbols)
+        .into_iter()
+        .filter(|variable| variable.kind == VariableKind::Spread)
+        .filter_map(|variable| symbol_table.get_variable(&variable.identifier))
+        // only local files can be watched
+        .filter(|location| !location.contains("://"))
+        .flat_map(|file_path| {
+            // NOTE: files added later that match the glob are not noticed
+            if is_glob(&file_path) {
+                glob_files(&file_path)
+            } else {
+                vec![file_path]
+            }
+        })
+        .map(path::PathBuf::from)
+        .collect()
+}
+
+/// Block until one of `files` is modified, created or removed
+pub fn wait_for_change(files: &[path::PathBuf]) {
+    let initial = modified_times(files);
+    while modified_times(files) == initial {
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn modified_times(files: &[path::PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file_path| fs::metadata(file_path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path;
+
+    use super::watched_files;
+
+    #[test]
+    fn test_watched_files() {
+        let symbol_table = ptemplator::SymbolTable::new(&[
+            ("snippet", "./test_corpus/snippet.txt"),
+            ("cycle", "./test_corpus/cycle.txt"),
+        ]);
+        let vars_files = vec![path::PathBuf::from("./test_corpus/vars.json")];
+        assert_eq!(
+            watched_files(
+                path::Path::new("./test_corpus/include.txt"),
+                &vars_files,
+                &symbol_table,
+                &Default::default()
+            ),
+            vec![
+                path::PathBuf::from("./test_corpus/include.txt"),
+                path::PathBuf::from("./test_corpus/vars.json"),
+                path::PathBuf::from("./test_corpus/snippet.txt"),
+            ]
+        );
+        // a file spreading itself is only watched once
+        assert_eq!(
+            watched_files(
+                path::Path::new("./test_corpus/cycle.txt"),
+                &[],
+                &symbol_table,
+                &Default::default()
+            ),
+            vec![path::PathBuf::from("./test_corpus/cycle.txt")]
+        );
+    }
+}